@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS options (
+    user_id BIGINT UNSIGNED NOT NULL PRIMARY KEY,
+    options JSON
+);
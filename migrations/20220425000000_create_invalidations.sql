@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS invalidations (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    topic VARCHAR(32) NOT NULL,
+    target_id BIGINT UNSIGNED NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX (topic, created_at),
+    INDEX (created_at)
+);
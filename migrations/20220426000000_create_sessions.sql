@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS sessions (
+    guild_id BIGINT UNSIGNED NOT NULL,
+    bot_id BIGINT UNSIGNED NOT NULL,
+    voice_channel_id BIGINT UNSIGNED NOT NULL,
+    text_channel_id BIGINT UNSIGNED NOT NULL,
+    PRIMARY KEY (guild_id, bot_id)
+);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS tts_usage (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    guild_id BIGINT UNSIGNED NOT NULL,
+    -- NULL for announcements made through the HTTP API.
+    user_id BIGINT UNSIGNED,
+    engine VARCHAR(32) NOT NULL,
+    characters INT UNSIGNED NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX (guild_id, created_at),
+    INDEX (user_id, created_at)
+);
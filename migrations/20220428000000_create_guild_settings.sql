@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS guild_settings (
+    guild_id BIGINT UNSIGNED NOT NULL PRIMARY KEY,
+    settings JSON NOT NULL
+);
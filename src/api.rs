@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{Extension, Path, Query};
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+
+use crate::metrics;
+use crate::option_builder::build_options;
+use crate::queue::{SpeechQueue, Utterance};
+use crate::session::SessionRegistry;
+use crate::tts;
+
+type ApiResult<T> = Result<T, (StatusCode, String)>;
+
+/// The speech queues of the sessions, by guild and bot account.
+pub type Queues = RwLock<HashMap<(GuildId, UserId), Arc<SpeechQueue>>>;
+
+/// An HTTP API for scripts to make the bot speak and to control its queues,
+/// which also exports Prometheus metrics. Every request must carry
+/// `Authorization: Bearer <token>`.
+pub struct Api {
+    token: String,
+    sessions: &'static RwLock<SessionRegistry>,
+    queues: &'static Queues,
+}
+
+impl Api {
+    pub fn new(
+        token: String,
+        sessions: &'static RwLock<SessionRegistry>,
+        queues: &'static Queues,
+    ) -> Self {
+        Self {
+            token,
+            sessions,
+            queues,
+        }
+    }
+
+    pub async fn serve(self, addr: SocketAddr) -> anyhow::Result<()> {
+        let app = Router::new()
+            .route("/metrics", get(export_metrics))
+            .route("/sessions", get(list_sessions))
+            .route("/guilds/:guild_id/speak", post(speak))
+            .route("/guilds/:guild_id/skip", post(skip))
+            .route("/guilds/:guild_id/clear", post(clear))
+            .layer(middleware::from_fn(authorize))
+            .layer(Extension(Arc::new(self)));
+        axum::Server::try_bind(&addr)?
+            .serve(app.into_make_service())
+            .await?;
+        Ok(())
+    }
+
+    /// The queues of the sessions in the guild, or only the one reading the
+    /// voice channel if it is given.
+    fn queues_in(
+        &self,
+        guild_id: GuildId,
+        voice_channel_id: Option<ChannelId>,
+    ) -> ApiResult<Vec<Arc<SpeechQueue>>> {
+        let sessions = self.sessions.read();
+        let queues = self.queues.read();
+        let found: Vec<_> = sessions
+            .sessions_in(guild_id)
+            .iter()
+            .filter(|s| voice_channel_id.map_or(true, |id| s.voice_channel_id == id))
+            .filter_map(|s| queues.get(&(guild_id, s.bot_id)).cloned())
+            .collect();
+        if found.is_empty() {
+            return Err((StatusCode::NOT_FOUND, "Not in a voice channel".to_string()));
+        }
+        Ok(found)
+    }
+}
+
+async fn authorize<B>(req: Request<B>, next: Next<B>) -> Response {
+    let authorized = {
+        let api = req.extensions().get::<Arc<Api>>().unwrap();
+        req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map_or(false, |token| token == api.token)
+    };
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(req).await
+}
+
+async fn export_metrics(Extension(api): Extension<Arc<Api>>) -> ApiResult<String> {
+    // Gauges of the current state are set when scraped rather than kept up to
+    // date, so that sessions that have gone don't linger.
+    {
+        let sessions = api.sessions.read();
+        let queues = api.queues.read();
+        metrics::QUEUE_DEPTH.reset();
+        let mut connections = 0;
+        for (guild_id, session) in sessions.iter() {
+            if let Some(queue) = queues.get(&(guild_id, session.bot_id)) {
+                metrics::QUEUE_DEPTH
+                    .with_label_values(&[&guild_id.to_string(), &session.bot_id.to_string()])
+                    .set(queue.len() as i64);
+                // The queue is paused while the voice connection is down.
+                if !queue.is_paused() {
+                    connections += 1;
+                }
+            }
+        }
+        metrics::VOICE_CONNECTIONS.set(connections);
+    }
+
+    metrics::render().map_err(|why| (StatusCode::INTERNAL_SERVER_ERROR, why.to_string()))
+}
+
+/// IDs are strings as in the Discord API, since they don't fit in a double.
+#[derive(Serialize)]
+struct SessionResponse {
+    guild_id: String,
+    bot_id: String,
+    voice_channel_id: String,
+    text_channel_id: String,
+    queue_length: usize,
+}
+
+async fn list_sessions(Extension(api): Extension<Arc<Api>>) -> Json<Vec<SessionResponse>> {
+    let sessions = api.sessions.read();
+    let queues = api.queues.read();
+    Json(
+        sessions
+            .iter()
+            .map(|(guild_id, session)| SessionResponse {
+                guild_id: guild_id.to_string(),
+                bot_id: session.bot_id.to_string(),
+                voice_channel_id: session.voice_channel_id.to_string(),
+                text_channel_id: session.text_channel_id.to_string(),
+                queue_length: queues
+                    .get(&(guild_id, session.bot_id))
+                    .map_or(0, |queue| queue.len()),
+            })
+            .collect(),
+    )
+}
+
+#[derive(Deserialize)]
+struct Target {
+    voice_channel_id: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct SpeakRequest {
+    text: String,
+    /// A preset name as in `.preset`.
+    preset: Option<String>,
+    /// An engine name with `key=value` options as in `.set`.
+    engine: Option<String>,
+    #[serde(default)]
+    options: Vec<String>,
+}
+
+impl SpeakRequest {
+    fn tts_options(&self) -> anyhow::Result<tts::Options> {
+        if let Some(preset) = &self.preset {
+            return Ok(tts::Preset::try_from(preset.as_str())?.into());
+        }
+        match &self.engine {
+            Some(engine) => {
+                let engine = tts::Engine::try_from(engine.as_str())?;
+                build_options(&engine, self.options.iter())
+            }
+            None => Ok(tts::Options::default()),
+        }
+    }
+}
+
+async fn speak(
+    Extension(api): Extension<Arc<Api>>,
+    Path(guild_id): Path<u64>,
+    Query(target): Query<Target>,
+    Json(req): Json<SpeakRequest>,
+) -> ApiResult<StatusCode> {
+    if req.text.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Text is empty".to_string()));
+    }
+    let options = req
+        .tts_options()
+        .map_err(|why| (StatusCode::BAD_REQUEST, why.to_string()))?;
+
+    let mut accepted = false;
+    for queue in api.queues_in(GuildId(guild_id), target.voice_channel_id.map(ChannelId))? {
+        accepted |= queue.push(Utterance {
+            text: req.text.clone(),
+            options: options.clone(),
+            guild_id: GuildId(guild_id),
+            author_id: None,
+            message_id: None,
+        });
+    }
+    if !accepted {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "Shutting down".to_string()));
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn skip(
+    Extension(api): Extension<Arc<Api>>,
+    Path(guild_id): Path<u64>,
+    Query(target): Query<Target>,
+) -> ApiResult<StatusCode> {
+    for queue in api.queues_in(GuildId(guild_id), target.voice_channel_id.map(ChannelId))? {
+        queue.skip();
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn clear(
+    Extension(api): Extension<Arc<Api>>,
+    Path(guild_id): Path<u64>,
+    Query(target): Query<Target>,
+) -> ApiResult<StatusCode> {
+    for queue in api.queues_in(GuildId(guild_id), target.voice_channel_id.map(ChannelId))? {
+        queue.clear();
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
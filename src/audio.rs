@@ -0,0 +1,130 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::bail;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::tts::voice_text::VoiceTextFormat;
+
+const TEMP_FILE_PREFIX: &str = "ttsbot_";
+
+const BEEP_FREQUENCY: u32 = 1000;
+const BEEP_SECONDS: f64 = 0.4;
+/// What the clips are resampled to before being concatenated.
+const CONCAT_SAMPLE_RATE: u32 = 24000;
+
+/// An audio file in the temporary directory, removed when dropped.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn create(data: &[u8]) -> io::Result<Self> {
+        // TODO: format
+        let path = env::temp_dir().join(format!("{}{}.wav", TEMP_FILE_PREFIX, Uuid::new_v4()));
+        let mut file = File::create(&path)?;
+        file.write_all(data)?;
+        file.flush()?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A part of the audio to be concatenated.
+pub enum Clip {
+    Audio(Vec<u8>),
+    /// A tone covering something that must not be read.
+    Beep,
+}
+
+/// Concatenates the clips into a WAV file with ffmpeg.
+pub async fn concat(clips: &[Clip]) -> anyhow::Result<Vec<u8>> {
+    let mut args: Vec<String> = vec!["-loglevel".into(), "error".into()];
+    // Kept until ffmpeg exits.
+    let mut files = Vec::new();
+    let mut filter = String::new();
+    for (i, clip) in clips.iter().enumerate() {
+        match clip {
+            Clip::Audio(audio) => {
+                let file = TempFile::create(audio)?;
+                args.push("-i".into());
+                args.push(file.path().to_string_lossy().into_owned());
+                files.push(file);
+            }
+            Clip::Beep => {
+                args.extend(["-f".into(), "lavfi".into(), "-i".into()]);
+                args.push(format!(
+                    "sine=frequency={}:duration={}",
+                    BEEP_FREQUENCY, BEEP_SECONDS
+                ));
+            }
+        }
+        filter.push_str(&format!(
+            "[{}:a]aresample={},aformat=sample_fmts=s16:channel_layouts=mono[a{}];",
+            i, CONCAT_SAMPLE_RATE, i
+        ));
+    }
+    for i in 0..clips.len() {
+        filter.push_str(&format!("[a{}]", i));
+    }
+    filter.push_str(&format!("concat=n={}:v=0:a=1", clips.len()));
+    args.extend([
+        "-filter_complex".into(),
+        filter,
+        "-f".into(),
+        "wav".into(),
+        "pipe:1".into(),
+    ]);
+
+    let output = Command::new("ffmpeg")
+        .args(&args)
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+    if !output.status.success() {
+        bail!(
+            "ffmpeg exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Converts audio into the format with ffmpeg.
+pub async fn transcode(audio: &[u8], format: &VoiceTextFormat) -> anyhow::Result<Vec<u8>> {
+    let mut child = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-i", "pipe:0", "-f"])
+        .arg(format.to_string())
+        .arg("pipe:1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Written from another task so that ffmpeg never blocks on a full stdout
+    // while we are still writing its stdin.
+    let mut stdin = child.stdin.take().unwrap();
+    let audio = audio.to_vec();
+    let writer = tokio::spawn(async move { stdin.write_all(&audio).await });
+
+    let output = child.wait_with_output().await?;
+    writer.await??;
+    if !output.status.success() {
+        bail!("ffmpeg exited with {}", output.status);
+    }
+    Ok(output.stdout)
+}
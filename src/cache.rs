@@ -0,0 +1,109 @@
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+use parking_lot::Mutex;
+use regex::Regex;
+
+/// Entries the caches of options and guild settings hold unless configured
+/// otherwise.
+pub const DEFAULT_CACHE_CAPACITY: usize = 10_000;
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(600);
+
+/// A bounded LRU cache whose entries expire after a fixed time-to-live.
+#[derive(Debug)]
+pub struct TtlCache<K: Hash + Eq, V> {
+    entries: LruCache<K, (V, Instant)>,
+    ttl: Duration,
+}
+
+impl<K: Hash + Eq, V: Clone> TtlCache<K, V> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: LruCache::new(capacity),
+            ttl,
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        match self.entries.get(key) {
+            Some((value, inserted_at)) if inserted_at.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                self.entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Like `get`, but the entry still expires at the time it would have.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        if matches!(self.entries.peek(key), Some((_, inserted_at)) if inserted_at.elapsed() >= self.ttl)
+        {
+            self.entries.pop(key);
+            return None;
+        }
+        self.entries.get_mut(key).map(|(value, _)| value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.entries.put(key, (value, Instant::now()));
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        self.entries.pop(key);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Regexes compiled once and shared, as compiling one costs far more than
+/// matching it.
+#[derive(Debug)]
+pub struct RegexCache {
+    compiled: Mutex<LruCache<String, Regex>>,
+}
+
+impl RegexCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            compiled: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn get(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        if let Some(regex) = self.compiled.lock().get(pattern) {
+            return Ok(regex.clone());
+        }
+        let regex = Regex::new(pattern)?;
+        self.compiled.lock().put(pattern.to_string(), regex.clone());
+        Ok(regex)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ttl_cache() {
+        let mut cache = TtlCache::new(2, Duration::from_secs(60));
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.get(&1), Some("a"));
+
+        // 2 is now the least recently used entry.
+        cache.insert(3, "c");
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some("a"));
+
+        cache.remove(&1);
+        assert_eq!(cache.get(&1), None);
+
+        let mut cache = TtlCache::new(2, Duration::ZERO);
+        cache.insert(1, "a");
+        assert_eq!(cache.get(&1), None);
+    }
+}
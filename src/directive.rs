@@ -0,0 +1,105 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use strum::IntoEnumIterator;
+
+use crate::option_builder::build_options;
+use crate::tts;
+
+/// A voice for a single message, at its start: a preset or a speaker in
+/// braces, `key=value` options as in `.set` in brackets, or both, as in
+/// `{zundamon} やったのだ` or `[speed=150] 早口`.
+static DIRECTIVE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s*(?:\{(?P<voice>[^{}\s]+)\})?\s*(?:\[(?P<args>[^\[\]]*=[^\[\]]*)\])?\s*")
+        .unwrap()
+});
+
+/// Names of the VOICEVOX speakers that are easier to type.
+const SPEAKER_NAMES: &[(&str, &str)] = &[
+    ("metan", "四国めたん"),
+    ("zundamon", "ずんだもん"),
+    ("tsumugi", "春日部つむぎ"),
+    ("hau", "雨晴はう"),
+    ("ritsu", "波音リツ"),
+    ("takehiro", "玄野武宏"),
+    ("kotarou", "白上虎太郎"),
+    ("ryusei", "青山龍星"),
+    ("himari", "冥鳴ひまり"),
+    ("sora", "九州そら"),
+];
+
+/// Splits the directive off the start of the text, returning the options it
+/// makes of `saved`, the options of the author, and the rest of the text. Text
+/// starting with no directive, or with one that is invalid, is left to be read
+/// as it is.
+pub fn apply_directive<'a>(text: &'a str, saved: &tts::Options) -> Option<(tts::Options, &'a str)> {
+    let caps = DIRECTIVE.captures(text)?;
+    let (voice, args) = (caps.name("voice"), caps.name("args"));
+    if voice.is_none() && args.is_none() {
+        return None;
+    }
+
+    let base = match voice {
+        Some(name) => voice_named(name.as_str())?,
+        None => saved.clone(),
+    };
+    let options = match args {
+        // The options of the base go first so that those given override them.
+        Some(args) => build_options(
+            &base.engine(),
+            base.args()
+                .into_iter()
+                .chain(args.as_str().split_whitespace().map(str::to_string)),
+        )
+        .ok()?,
+        None => base,
+    };
+    Some((options, &text[caps[0].len()..]))
+}
+
+/// The options of a preset or a speaker of any engine, by its name.
+fn voice_named(name: &str) -> Option<tts::Options> {
+    if let Ok(preset) = tts::Preset::try_from(name) {
+        return Some(preset.into());
+    }
+    let name = SPEAKER_NAMES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, name)| name);
+    tts::Engine::iter().find_map(|engine| {
+        build_options(&engine, std::iter::once(format!("speaker={}", name))).ok()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::option_builder::build_voice_vox_options;
+
+    #[test]
+    fn test_apply_directive() {
+        let saved = tts::Options::default();
+        let zundamon = build_voice_vox_options(["speaker=ずんだもん"].iter()).unwrap();
+
+        let (options, rest) = apply_directive("{zundamon} やったのだ", &saved).unwrap();
+        assert_eq!(options, tts::Options::VoiceVoxOptions(zundamon));
+        assert_eq!(rest, "やったのだ");
+
+        let (options, rest) = apply_directive("[speed=150] 早口", &saved).unwrap();
+        assert!(options.args().contains(&"speed=150".to_string()));
+        assert!(options.args().contains(&"speaker=show".to_string()));
+        assert_eq!(rest, "早口");
+
+        let (options, _) = apply_directive("{munou}[speed=200]あ", &saved).unwrap();
+        assert!(options.args().contains(&"pitch=150".to_string()));
+        assert!(options.args().contains(&"speed=200".to_string()));
+
+        assert!(apply_directive("[speed=1000] 速すぎ", &saved).is_none());
+        // As fast as VoiceText allows, but 150 times the speed for VOICEVOX.
+        assert!(apply_directive("{zundamon}[speed=150] 速すぎ", &saved).is_none());
+        assert!(apply_directive("{zundamon}[speed=1.5] 速い", &saved).is_some());
+        assert!(apply_directive("{nobody} 誰", &saved).is_none());
+        assert!(apply_directive("[笑] 普通の文", &saved).is_none());
+        assert!(apply_directive("普通の文 {zundamon}", &saved).is_none());
+    }
+}
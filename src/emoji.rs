@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+
+/// Japanese names of emoji, one `emoji<TAB>name` per line.
+static NAMES: Lazy<HashMap<&'static str, &'static str>> =
+    Lazy::new(|| parse(include_str!("emoji_ja.tsv")));
+
+/// Readings of kaomoji, longest first so that "(^^;" is not read as "(^^".
+static KAOMOJI: Lazy<Vec<(&'static str, &'static str)>> = Lazy::new(|| {
+    let mut kaomoji: Vec<_> = parse(include_str!("kaomoji.tsv")).into_iter().collect();
+    kaomoji.sort_by_key(|(kaomoji, _)| std::cmp::Reverse(kaomoji.chars().count()));
+    kaomoji
+});
+
+fn parse(table: &'static str) -> HashMap<&'static str, &'static str> {
+    table
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('\t'))
+        .collect()
+}
+
+const VARIATION_SELECTOR: char = '\u{FE0F}';
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+const KEYCAP: char = '\u{20E3}';
+
+/// Read in place of emoji missing from the table.
+const UNKNOWN: &str = "絵文字";
+
+/// What to do with the emoji in a message.
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq, Eq, Serialize, Deserialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum EmojiMode {
+    /// Read each emoji by its name.
+    Read,
+    /// Leave them out.
+    Drop,
+    /// Read how many there are, as in "絵文字3個".
+    Count,
+}
+
+impl Default for EmojiMode {
+    fn default() -> Self {
+        EmojiMode::Read
+    }
+}
+
+/// Replaces the emoji in the text as `mode` says. A run of emoji is read at
+/// once, and the same emoji repeated in a run is read once.
+pub fn replace_emoji(text: &str, mode: EmojiMode) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut replaced = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let mut names = Vec::new();
+        while let Some((length, name)) = emoji_at(&chars[i..]) {
+            names.push(name);
+            i += length;
+        }
+        if names.is_empty() {
+            // Left over from an emoji sequence the engines can't read anyway.
+            if !matches!(chars[i], VARIATION_SELECTOR | KEYCAP) && !is_skin_tone(chars[i]) {
+                replaced.push(chars[i]);
+            }
+            i += 1;
+            continue;
+        }
+
+        match mode {
+            EmojiMode::Read => {
+                names.dedup();
+                replaced.push_str(&names.join("、"));
+            }
+            EmojiMode::Drop => {}
+            EmojiMode::Count => replaced.push_str(&format!("絵文字{}個", names.len())),
+        }
+    }
+    replaced
+}
+
+/// The length and the name of the emoji the characters start with, if any.
+fn emoji_at(chars: &[char]) -> Option<(usize, String)> {
+    let first = *chars.first()?;
+    if is_regional_indicator(first) {
+        return match chars.get(1) {
+            Some(&second) if is_regional_indicator(second) => {
+                let flag: String = [first, second].iter().collect();
+                let name = NAMES.get(flag.as_str()).copied().unwrap_or("旗");
+                Some((2, name.to_string()))
+            }
+            _ => None,
+        };
+    }
+    if first.is_ascii() {
+        // A keycap such as "1️⃣" is read as the character on it.
+        return match chars.get(1..3) {
+            Some([VARIATION_SELECTOR, KEYCAP]) => Some((3, first.to_string())),
+            Some([KEYCAP, ..]) => Some((2, first.to_string())),
+            _ => None,
+        };
+    }
+
+    let mut key = String::new();
+    key.push(first);
+    let mut i = 1;
+    loop {
+        while matches!(chars.get(i), Some(&c) if c == VARIATION_SELECTOR || is_skin_tone(c)) {
+            i += 1;
+        }
+        match (chars.get(i), chars.get(i + 1)) {
+            (Some(&ZERO_WIDTH_JOINER), Some(&next))
+                if is_pictographic(next) || NAMES.contains_key(next.to_string().as_str()) =>
+            {
+                key.push(ZERO_WIDTH_JOINER);
+                key.push(next);
+                i += 2;
+            }
+            _ => break,
+        }
+    }
+
+    let is_emoji = is_pictographic(first)
+        || NAMES.contains_key(first.to_string().as_str())
+        || chars.get(1) == Some(&VARIATION_SELECTOR);
+    if !is_emoji {
+        return None;
+    }
+    // A sequence missing from the table is read by its first emoji, as in
+    // "👩‍🍳" read as "👩".
+    let name = NAMES
+        .get(key.as_str())
+        .or_else(|| NAMES.get(first.to_string().as_str()))
+        .copied()
+        .unwrap_or(UNKNOWN);
+    Some((i, name.to_string()))
+}
+
+fn is_pictographic(c: char) -> bool {
+    matches!(c, '\u{1F000}'..='\u{1FAFF}') && !is_regional_indicator(c) && !is_skin_tone(c)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c, '\u{1F1E6}'..='\u{1F1FF}')
+}
+
+fn is_skin_tone(c: char) -> bool {
+    matches!(c, '\u{1F3FB}'..='\u{1F3FF}')
+}
+
+/// Replaces the kaomoji in the table with their readings.
+pub fn replace_kaomoji(text: &str) -> String {
+    let mut replaced = text.to_string();
+    for (kaomoji, reading) in KAOMOJI.iter() {
+        if replaced.contains(kaomoji) {
+            replaced = replaced.replace(kaomoji, reading);
+        }
+    }
+    replaced
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_replace_emoji() {
+        assert_eq!(replace_emoji("笑😂", EmojiMode::Read), "笑嬉し泣きの顔");
+        assert_eq!(replace_emoji("🎉🎉🎉", EmojiMode::Read), "クラッカー");
+        assert_eq!(replace_emoji("👍🏻🔥", EmojiMode::Read), "サムズアップ、火");
+        assert_eq!(replace_emoji("❤️", EmojiMode::Read), "赤いハート");
+        assert_eq!(replace_emoji("🇯🇵", EmojiMode::Read), "国旗: 日本");
+        assert_eq!(replace_emoji("👨‍💻", EmojiMode::Read), "技術者の男性");
+        assert_eq!(replace_emoji("1️⃣", EmojiMode::Read), "1");
+        assert_eq!(replace_emoji("☆", EmojiMode::Read), "☆");
+        assert_eq!(replace_emoji("やった🎉🎉🎉", EmojiMode::Drop), "やった");
+        assert_eq!(
+            replace_emoji("やった🎉🎉🎉", EmojiMode::Count),
+            "やった絵文字3個"
+        );
+    }
+
+    #[test]
+    fn test_replace_kaomoji() {
+        assert_eq!(replace_kaomoji("ごめん(^^;"), "ごめんあせあせ");
+        assert_eq!(replace_kaomoji("よろしくm(_ _)m"), "よろしくごめんなさい");
+    }
+}
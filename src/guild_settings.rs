@@ -0,0 +1,467 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use parking_lot::Mutex;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{GuildId, RoleId};
+use sqlx::mysql::MySqlPool;
+use strum::{Display, EnumString};
+
+use crate::cache::{RegexCache, TtlCache, DEFAULT_CACHE_CAPACITY};
+use crate::emoji::EmojiMode;
+use crate::invalidation::Invalidations;
+use crate::pipeline::ChatMessage;
+
+/// What the admins of a guild have configured.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// Words never read, which are beeped out instead. An entry enclosed in
+    /// slashes, as in `/ba+ka/`, is a regex.
+    pub ng_words: Vec<String>,
+    /// Applied in order before anything else is done to the text.
+    pub rules: Vec<Rule>,
+    /// Whether English words are read in katakana rather than spelled out.
+    pub english_to_kana: bool,
+    pub emoji: EmojiMode,
+    /// Whether kaomoji such as "(^^;" are read as what they express.
+    pub kaomoji: bool,
+    pub reply: ReplyMode,
+    /// Whether the embeds of messages posted by other bots, such as chat
+    /// bridges, are read, once `ignore_bots` is off.
+    pub bot_embeds: bool,
+    /// Messages starting with any of these are not read, in addition to the
+    /// commands of this bot.
+    pub ignore_prefixes: Vec<String>,
+    /// On unless turned off, so that other bots are read only by choice.
+    pub ignore_bots: bool,
+    /// On unless turned off, as with `ignore_bots`.
+    pub ignore_webhooks: bool,
+    /// Users whose messages an admin has chosen not to read.
+    pub ignored_users: Vec<u64>,
+    pub ignored_roles: Vec<u64>,
+    /// Users who have chosen not to have their messages read.
+    pub opted_out: Vec<u64>,
+    /// Roles whose members may change the settings as if they had Manage
+    /// Guild.
+    pub dj_roles: Vec<u64>,
+    /// Whether only those in the voice channel of the bot, besides admins, may
+    /// make it leave, mute or stop.
+    pub restrict_voice_control: bool,
+    /// Characters the guild can have read with paid engines per day, in place
+    /// of the default of the bot.
+    pub daily_quota: Option<u64>,
+    /// Characters the guild can have read with paid engines per month, in
+    /// place of the default of the bot.
+    pub monthly_quota: Option<u64>,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        GuildSettings {
+            ng_words: Vec::new(),
+            rules: Vec::new(),
+            english_to_kana: false,
+            emoji: EmojiMode::default(),
+            kaomoji: false,
+            reply: ReplyMode::default(),
+            bot_embeds: false,
+            ignore_prefixes: Vec::new(),
+            ignore_bots: true,
+            ignore_webhooks: true,
+            ignored_users: Vec::new(),
+            ignored_roles: Vec::new(),
+            opted_out: Vec::new(),
+            dj_roles: Vec::new(),
+            restrict_voice_control: false,
+            daily_quota: None,
+            monthly_quota: None,
+        }
+    }
+}
+
+/// How a reply tells what it responds to.
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq, Eq, Serialize, Deserialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReplyMode {
+    /// Not at all.
+    Off,
+    /// With "○○さんへの返信".
+    Name,
+    /// With "○○さんへの返信" and the start of the original message.
+    Excerpt,
+}
+
+impl Default for ReplyMode {
+    fn default() -> Self {
+        ReplyMode::Off
+    }
+}
+
+impl GuildSettings {
+    /// The settings that can be changed with `set`, with their values.
+    pub fn values(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("english_to_kana", on_off(self.english_to_kana)),
+            ("emoji", self.emoji.to_string()),
+            ("kaomoji", on_off(self.kaomoji)),
+            ("reply", self.reply.to_string()),
+            ("bot_embeds", on_off(self.bot_embeds)),
+            ("ignore_bots", on_off(self.ignore_bots)),
+            ("ignore_webhooks", on_off(self.ignore_webhooks)),
+            (
+                "restrict_voice_control",
+                on_off(self.restrict_voice_control),
+            ),
+            ("daily_quota", quota_value(self.daily_quota)),
+            ("monthly_quota", quota_value(self.monthly_quota)),
+        ]
+    }
+
+    /// Whether the message is not to be read by the ignore rules.
+    pub fn ignores(&self, msg: &ChatMessage) -> bool {
+        let author_id = msg.author_id.0;
+        msg.content.starts_with('.')
+            || self
+                .ignore_prefixes
+                .iter()
+                .any(|prefix| msg.content.starts_with(prefix.as_str()))
+            // Webhooks post as bots, but are told apart from them.
+            || (msg.is_webhook && self.ignore_webhooks)
+            || (msg.author_is_bot && !msg.is_webhook && self.ignore_bots)
+            || self.ignored_users.contains(&author_id)
+            || self.opted_out.contains(&author_id)
+            || msg
+                .author_roles
+                .iter()
+                .any(|role_id| self.ignored_roles.contains(&role_id.0))
+    }
+
+    /// Whether any of the roles is one of `dj_roles`.
+    pub fn is_dj(&self, roles: &[RoleId]) -> bool {
+        roles
+            .iter()
+            .any(|role_id| self.dj_roles.contains(&role_id.0))
+    }
+
+    /// Changes a setting by its name, as in `.config english_to_kana on`.
+    pub fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        match key {
+            "english_to_kana" => self.english_to_kana = parse_on_off(value)?,
+            "emoji" => {
+                self.emoji = EmojiMode::try_from(value)
+                    .map_err(|_| anyhow!("Expected read, drop or count: {}", value))?
+            }
+            "kaomoji" => self.kaomoji = parse_on_off(value)?,
+            "reply" => {
+                self.reply = ReplyMode::try_from(value)
+                    .map_err(|_| anyhow!("Expected off, name or excerpt: {}", value))?
+            }
+            "bot_embeds" => self.bot_embeds = parse_on_off(value)?,
+            "ignore_bots" => self.ignore_bots = parse_on_off(value)?,
+            "ignore_webhooks" => self.ignore_webhooks = parse_on_off(value)?,
+            "restrict_voice_control" => self.restrict_voice_control = parse_on_off(value)?,
+            "daily_quota" => self.daily_quota = parse_quota(value)?,
+            "monthly_quota" => self.monthly_quota = parse_quota(value)?,
+            _ => bail!("Unknown setting: {}", key),
+        }
+        Ok(())
+    }
+}
+
+fn on_off(value: bool) -> String {
+    if value { "on" } else { "off" }.to_string()
+}
+
+fn parse_on_off(value: &str) -> anyhow::Result<bool> {
+    match value {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => bail!("Expected on or off: {}", value),
+    }
+}
+
+fn quota_value(value: Option<u64>) -> String {
+    value.map_or("default".to_string(), |limit| limit.to_string())
+}
+
+fn parse_quota(value: &str) -> anyhow::Result<Option<u64>> {
+    match value {
+        "default" => Ok(None),
+        _ => Ok(Some(value.parse().map_err(|_| {
+            anyhow!("Expected a number of characters or default: {}", value)
+        })?)),
+    }
+}
+
+/// Rewrites what `pattern` matches to `replacement`, in which `$1` and `$name`
+/// stand for the groups captured.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+/// The regex of an entry of `ng_words`, matching plain words
+/// case-insensitively.
+pub fn ng_word_pattern(word: &str) -> String {
+    match word.strip_prefix('/').and_then(|w| w.strip_suffix('/')) {
+        Some(pattern) if !pattern.is_empty() => pattern.to_string(),
+        _ => format!("(?i){}", regex::escape(word)),
+    }
+}
+
+pub struct GuildSettingsStorage {
+    cache: Mutex<TtlCache<u64, Arc<GuildSettings>>>,
+    patterns: RegexCache,
+    /// `None` keeps the settings in memory only, as in tests.
+    database: Option<(MySqlPool, Invalidations)>,
+}
+
+impl GuildSettingsStorage {
+    pub async fn new(
+        pool: MySqlPool,
+        cache_capacity: usize,
+        cache_ttl: Duration,
+    ) -> anyhow::Result<Self> {
+        let invalidations = Invalidations::follow(&pool, "guild_settings").await?;
+        Ok(Self {
+            cache: Mutex::new(TtlCache::new(cache_capacity, cache_ttl)),
+            patterns: RegexCache::new(cache_capacity),
+            database: Some((pool, invalidations)),
+        })
+    }
+
+    /// Settings kept in memory only, which never expire.
+    pub fn in_memory() -> Self {
+        Self {
+            cache: Mutex::new(TtlCache::new(DEFAULT_CACHE_CAPACITY, Duration::MAX)),
+            patterns: RegexCache::new(DEFAULT_CACHE_CAPACITY),
+            database: None,
+        }
+    }
+
+    pub async fn get(&self, guild_id: GuildId) -> anyhow::Result<Arc<GuildSettings>> {
+        if let Some(settings) = self.cache.lock().get(&guild_id.0) {
+            return Ok(settings);
+        }
+        let (pool, _) = match &self.database {
+            Some(database) => database,
+            None => return Ok(Arc::default()),
+        };
+
+        let record = sqlx::query!(
+            "SELECT settings FROM guild_settings WHERE guild_id = ?",
+            guild_id.0
+        )
+        .fetch_optional(pool)
+        .await?;
+        let settings: Arc<GuildSettings> = match record {
+            Some(record) => Arc::new(serde_json::from_value(record.settings)?),
+            None => Arc::default(),
+        };
+        self.cache.lock().insert(guild_id.0, settings.clone());
+
+        Ok(settings)
+    }
+
+    /// Changes the settings of the guild, returning the new ones. Nothing is
+    /// changed if `f` fails.
+    pub async fn update<F>(&self, guild_id: GuildId, f: F) -> anyhow::Result<Arc<GuildSettings>>
+    where
+        F: FnOnce(&mut GuildSettings) -> anyhow::Result<()>,
+    {
+        let (pool, invalidations) = match &self.database {
+            Some(database) => database,
+            None => {
+                let mut cache = self.cache.lock();
+                let mut settings = cache
+                    .get(&guild_id.0)
+                    .map_or_else(GuildSettings::default, |settings| (*settings).clone());
+                f(&mut settings)?;
+                let settings = Arc::new(settings);
+                cache.insert(guild_id.0, settings.clone());
+                return Ok(settings);
+            }
+        };
+
+        // The row is locked until the change is committed so that changes made
+        // at once by several processes are applied one after another. It is
+        // created first since locking a missing one would not stop another
+        // process from inserting it.
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            "INSERT IGNORE INTO guild_settings (guild_id, settings) VALUES (?, '{}')",
+            guild_id.0
+        )
+        .execute(&mut tx)
+        .await?;
+        let record = sqlx::query!(
+            "SELECT settings FROM guild_settings WHERE guild_id = ? FOR UPDATE",
+            guild_id.0
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let mut settings: GuildSettings = serde_json::from_value(record.settings)?;
+        f(&mut settings)?;
+
+        sqlx::query!(
+            "UPDATE guild_settings SET settings = ? WHERE guild_id = ?",
+            serde_json::to_string(&settings)?,
+            guild_id.0
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        invalidations.publish(pool, guild_id.0).await?;
+        let settings = Arc::new(settings);
+        self.cache.lock().insert(guild_id.0, settings.clone());
+        Ok(settings)
+    }
+
+    /// The compiled `ng_words` of the settings. Entries that fail to compile
+    /// are skipped.
+    pub fn ng_words(&self, settings: &GuildSettings) -> Vec<Regex> {
+        settings
+            .ng_words
+            .iter()
+            .filter_map(|word| self.patterns.get(&ng_word_pattern(word)).ok())
+            .collect()
+    }
+
+    /// The compiled `rules` of the settings. Rules that fail to compile are
+    /// skipped.
+    pub fn rules(&self, settings: &GuildSettings) -> Vec<(Regex, String)> {
+        settings
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                let regex = self.patterns.get(&rule.pattern).ok()?;
+                Some((regex, rule.replacement.clone()))
+            })
+            .collect()
+    }
+
+    /// Invalidates the settings changed by other processes sharing the
+    /// database. Meant to be called periodically.
+    pub async fn sync(&self) -> anyhow::Result<()> {
+        let (pool, invalidations) = match &self.database {
+            Some(database) => database,
+            None => return Ok(()),
+        };
+        let guild_ids = invalidations.poll(pool).await?;
+        let mut cache = self.cache.lock();
+        for guild_id in guild_ids {
+            cache.remove(&guild_id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serenity::model::id::{ChannelId, MessageId, UserId};
+
+    #[test]
+    fn test_set() {
+        let mut settings = GuildSettings::default();
+        settings.set("english_to_kana", "on").unwrap();
+        assert!(settings.english_to_kana);
+        assert!(settings.set("english_to_kana", "maybe").is_err());
+        settings.set("emoji", "count").unwrap();
+        assert_eq!(settings.emoji, EmojiMode::Count);
+        assert!(settings.set("emoji", "sing").is_err());
+        settings.set("daily_quota", "5000").unwrap();
+        assert_eq!(settings.daily_quota, Some(5000));
+        settings.set("daily_quota", "default").unwrap();
+        assert_eq!(settings.daily_quota, None);
+        assert!(settings.set("monthly_quota", "-1").is_err());
+        assert!(settings.set("unknown", "on").is_err());
+    }
+
+    #[test]
+    fn test_ignores() {
+        let msg = ChatMessage {
+            id: MessageId(1),
+            guild_id: GuildId(1),
+            channel_id: ChannelId(1),
+            author_id: UserId(1),
+            author_is_bot: true,
+            author_roles: vec![RoleId(1)],
+            is_webhook: false,
+            content: "!play".to_string(),
+            text: "!play".to_string(),
+            reply: None,
+            extras: Vec::new(),
+            embeds: Vec::new(),
+            edited: false,
+        };
+        let mut settings = GuildSettings::default();
+        assert!(settings.ignores(&msg));
+
+        settings.ignore_webhooks = false;
+        assert!(settings.ignores(&msg));
+        settings.ignore_bots = false;
+        assert!(!settings.ignores(&msg));
+
+        let webhook = ChatMessage {
+            is_webhook: true,
+            ..msg.clone()
+        };
+        assert!(!settings.ignores(&webhook));
+        assert!(GuildSettings::default().ignores(&webhook));
+
+        let reading_bots = GuildSettings {
+            ignore_bots: false,
+            ..Default::default()
+        };
+        let settings = GuildSettings {
+            ignore_prefixes: vec!["!".to_string()],
+            ..reading_bots.clone()
+        };
+        assert!(settings.ignores(&msg));
+
+        let settings = GuildSettings {
+            ignored_roles: vec![1],
+            ..reading_bots.clone()
+        };
+        assert!(settings.ignores(&msg));
+
+        let settings = GuildSettings {
+            opted_out: vec![1],
+            ..reading_bots
+        };
+        assert!(settings.ignores(&msg));
+
+        // Settings saved before these existed read bots only by choice too.
+        let saved: GuildSettings = serde_json::from_str("{}").unwrap();
+        assert!(saved.ignore_bots && saved.ignore_webhooks);
+    }
+
+    #[test]
+    fn test_is_dj() {
+        let settings = GuildSettings {
+            dj_roles: vec![2],
+            ..Default::default()
+        };
+        assert!(settings.is_dj(&[RoleId(1), RoleId(2)]));
+        assert!(!settings.is_dj(&[RoleId(1)]));
+        assert!(!settings.is_dj(&[]));
+    }
+
+    #[test]
+    fn test_ng_word_pattern() {
+        let compile = |word| Regex::new(&ng_word_pattern(word));
+        assert!(compile("Baka").unwrap().is_match("ばか BAKA"));
+        assert!(!compile("a.c").unwrap().is_match("abc"));
+        assert!(compile("/a.c/").unwrap().is_match("abc"));
+        assert!(compile("/(/").is_err());
+        // Not a regex, but a word made of a slash.
+        assert!(compile("/").unwrap().is_match("a/b"));
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use sqlx::mysql::MySqlPool;
+
+/// Seconds each poll looks back past the latest change it has seen. Ids are
+/// allocated before the rows commit, so a change may become visible after later
+/// ones; looking back by time rather than by id keeps it from being missed.
+const OVERLAP: u64 = 60;
+
+/// How often a process deletes the changes everyone has long since seen.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A change feed stored in the `invalidations` table that lets several bot
+/// processes sharing one database drop stale cache entries of each other.
+#[derive(Debug)]
+pub(crate) struct Invalidations {
+    topic: &'static str,
+    /// When the latest change seen was made, in Unix seconds of the database
+    /// clock.
+    cursor: AtomicU64,
+    /// The changes seen within the overlap, by id, with when they were made.
+    seen: Mutex<HashMap<u64, u64>>,
+    last_pruned: Mutex<Instant>,
+}
+
+impl Invalidations {
+    /// Starts following `topic` from now. The first poll may return the changes
+    /// of the last moments before, which only drops entries again.
+    pub(crate) async fn follow(pool: &MySqlPool, topic: &'static str) -> anyhow::Result<Self> {
+        let record = sqlx::query!(r#"SELECT CAST(UNIX_TIMESTAMP() AS UNSIGNED) AS "now!: u64""#)
+            .fetch_one(pool)
+            .await?;
+        Ok(Self {
+            topic,
+            cursor: AtomicU64::new(record.now),
+            seen: Mutex::new(HashMap::new()),
+            last_pruned: Mutex::new(Instant::now()),
+        })
+    }
+
+    pub(crate) async fn publish(&self, pool: &MySqlPool, target_id: u64) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO invalidations (topic, target_id) VALUES (?, ?)",
+            self.topic,
+            target_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the targets changed since the last poll, including the ones
+    /// published by this process.
+    pub(crate) async fn poll(&self, pool: &MySqlPool) -> anyhow::Result<Vec<u64>> {
+        let since = self.cursor.load(Ordering::SeqCst).saturating_sub(OVERLAP);
+        let records = sqlx::query!(
+            r#"
+SELECT id, target_id, CAST(UNIX_TIMESTAMP(created_at) AS UNSIGNED) AS "created_at!: u64"
+FROM invalidations
+WHERE topic = ? AND created_at >= FROM_UNIXTIME(?)
+ORDER BY id
+            "#,
+            self.topic,
+            since
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut target_ids = Vec::new();
+        {
+            let mut seen = self.seen.lock();
+            for record in records {
+                self.cursor.fetch_max(record.created_at, Ordering::SeqCst);
+                if seen.insert(record.id, record.created_at).is_none() {
+                    target_ids.push(record.target_id);
+                }
+            }
+            // What the next poll no longer looks back at can't be seen again.
+            let since = self.cursor.load(Ordering::SeqCst).saturating_sub(OVERLAP);
+            seen.retain(|_, created_at| *created_at >= since);
+        }
+
+        let prune = {
+            let mut last_pruned = self.last_pruned.lock();
+            let prune = last_pruned.elapsed() >= PRUNE_INTERVAL;
+            if prune {
+                *last_pruned = Instant::now();
+            }
+            prune
+        };
+        if prune {
+            sqlx::query!("DELETE FROM invalidations WHERE created_at < NOW() - INTERVAL 1 DAY")
+                .execute(pool)
+                .await?;
+        }
+
+        Ok(target_ids)
+    }
+}
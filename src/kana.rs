@@ -0,0 +1,461 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+/// Readings of English words, one `word<TAB>reading` per line with the word in
+/// lowercase.
+static DICTIONARY: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
+    include_str!("english_kana.tsv")
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('\t'))
+        .collect()
+});
+
+/// A run of what can make up a word, a URL, an address or a version.
+static TOKEN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"[A-Za-z0-9_'.,:;!?/@#$%&=+~-]*[A-Za-z][A-Za-z0-9_'.,:;!?/@#$%&=+~-]*").unwrap()
+});
+
+static WORD: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Za-z]+(?:'[A-Za-z]+)?$").unwrap());
+
+const LETTERS: [&str; 26] = [
+    "エー",
+    "ビー",
+    "シー",
+    "ディー",
+    "イー",
+    "エフ",
+    "ジー",
+    "エイチ",
+    "アイ",
+    "ジェー",
+    "ケー",
+    "エル",
+    "エム",
+    "エヌ",
+    "オー",
+    "ピー",
+    "キュー",
+    "アール",
+    "エス",
+    "ティー",
+    "ユー",
+    "ブイ",
+    "ダブリュー",
+    "エックス",
+    "ワイ",
+    "ゼット",
+];
+
+/// Acronyms up to this long are spelled out, as in "API".
+const MAX_ACRONYM_LENGTH: usize = 5;
+
+/// Replaces the English words in the text with katakana so that Japanese voices
+/// read them as words rather than letter by letter. Anything that isn't a plain
+/// word, such as a URL, is left as it is.
+pub fn english_to_kana(text: &str) -> String {
+    TOKEN
+        .replace_all(text, |caps: &Captures| {
+            let token = &caps[0];
+            // Punctuation ending a sentence isn't part of the word.
+            let word = token.trim_end_matches(&['.', ',', ':', ';', '!', '?'][..]);
+            if WORD.is_match(word) {
+                read_word(word) + &token[word.len()..]
+            } else {
+                token.to_string()
+            }
+        })
+        .into_owned()
+}
+
+fn read_word(word: &str) -> String {
+    if let Some(reading) = DICTIONARY.get(word.to_lowercase().as_str()) {
+        return reading.to_string();
+    }
+    let word = word.replace('\'', "");
+    if is_acronym(&word) {
+        return spell(&word);
+    }
+    // "GitLab" or "useState" is read part by part.
+    camel_case_parts(&word)
+        .into_iter()
+        .map(|part| match DICTIONARY.get(part.to_lowercase().as_str()) {
+            Some(reading) => reading.to_string(),
+            None if is_acronym(part) => spell(part),
+            None => transliterate(&part.to_lowercase()),
+        })
+        .collect()
+}
+
+/// Splits the word before each capital letter that starts a part, as in
+/// "HTML|Parser" or "use|State".
+fn camel_case_parts(word: &str) -> Vec<&str> {
+    let bytes = word.as_bytes();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for i in 1..bytes.len() {
+        let starts_part = bytes[i].is_ascii_uppercase()
+            && (bytes[i - 1].is_ascii_lowercase()
+                || bytes.get(i + 1).map_or(false, u8::is_ascii_lowercase));
+        if starts_part {
+            parts.push(&word[start..i]);
+            start = i;
+        }
+    }
+    parts.push(&word[start..]);
+    parts
+}
+
+fn is_acronym(word: &str) -> bool {
+    let upper = word.chars().all(|c| c.is_ascii_uppercase());
+    let vowels = word.chars().any(|c| "aeiouyAEIOUY".contains(c));
+    (upper && word.len() <= MAX_ACRONYM_LENGTH) || !vowels
+}
+
+fn spell(word: &str) -> String {
+    word.chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|c| LETTERS[(c.to_ascii_lowercase() as u8 - b'a') as usize])
+        .collect()
+}
+
+/// Kana for each consonant followed by "a", "i", "u", "e" and "o".
+fn row(consonant: &str) -> [&'static str; 5] {
+    match consonant {
+        "k" | "c" | "q" => ["カ", "キ", "ク", "ケ", "コ"],
+        "g" => ["ガ", "ギ", "グ", "ゲ", "ゴ"],
+        "s" | "th" => ["サ", "シ", "ス", "セ", "ソ"],
+        "z" => ["ザ", "ジ", "ズ", "ゼ", "ゾ"],
+        "t" => ["タ", "ティ", "トゥ", "テ", "ト"],
+        "d" => ["ダ", "ディ", "ドゥ", "デ", "ド"],
+        "n" => ["ナ", "ニ", "ヌ", "ネ", "ノ"],
+        "h" => ["ハ", "ヒ", "フ", "ヘ", "ホ"],
+        "b" => ["バ", "ビ", "ブ", "ベ", "ボ"],
+        "p" => ["パ", "ピ", "プ", "ペ", "ポ"],
+        "m" => ["マ", "ミ", "ム", "メ", "モ"],
+        "y" => ["ヤ", "イ", "ユ", "イエ", "ヨ"],
+        "r" | "l" => ["ラ", "リ", "ル", "レ", "ロ"],
+        "w" | "wh" => ["ワ", "ウィ", "ウ", "ウェ", "ウォ"],
+        "f" | "ph" => ["ファ", "フィ", "フ", "フェ", "フォ"],
+        "v" => ["バ", "ビ", "ブ", "ベ", "ボ"],
+        "j" => ["ジャ", "ジ", "ジュ", "ジェ", "ジョ"],
+        "ch" => ["チャ", "チ", "チュ", "チェ", "チョ"],
+        "sh" => ["シャ", "シ", "シュ", "シェ", "ショ"],
+        _ => ["ア", "イ", "ウ", "エ", "オ"],
+    }
+}
+
+/// Kana for a consonant not followed by a vowel.
+fn lone(consonant: &str) -> &'static str {
+    match consonant {
+        "" | "h" => "",
+        "t" => "ト",
+        "d" => "ド",
+        "n" => "ン",
+        "ch" => "チ",
+        "sh" => "シュ",
+        "j" => "ジ",
+        "x" => "クス",
+        "y" => "イ",
+        consonant => row(consonant)[2],
+    }
+}
+
+/// Kana for a vowel read as its name, as in "make", "time", "cute", "these" or
+/// "note".
+fn long(consonant: &str, vowel: usize) -> String {
+    let row = row(consonant);
+    match vowel {
+        0 => format!("{}イ", row[3]),
+        1 => format!("{}イ", row[0]),
+        2 => long_u(consonant).to_string(),
+        3 => format!("{}ー", row[1]),
+        _ => format!("{}ー", row[4]),
+    }
+}
+
+/// Kana for "u" read as its name, which glides from "i" after most consonants.
+fn long_u(consonant: &str) -> &'static str {
+    match consonant {
+        "" | "y" => "ユー",
+        "k" | "c" | "q" => "キュー",
+        "g" => "ギュー",
+        "m" => "ミュー",
+        "n" => "ニュー",
+        "h" => "ヒュー",
+        "b" | "v" => "ビュー",
+        "p" => "ピュー",
+        "f" | "ph" => "フュー",
+        "t" | "ch" => "チュー",
+        "d" => "デュー",
+        "j" => "ジュー",
+        "sh" => "シュー",
+        "s" | "th" => "スー",
+        "z" => "ズー",
+        "r" | "l" => "ルー",
+        _ => "ウー",
+    }
+}
+
+/// Whether the vowel before `i` is followed by one consonant and an "e" ending
+/// the word, which makes it read as its name, as in "make". Words like "have"
+/// or "come" are exceptions left to the dictionary.
+fn before_silent_e(bytes: &[u8], i: usize) -> bool {
+    matches!(bytes.get(i), Some(c) if !is_vowel(*c) && !b"hrvwxy".contains(c))
+        && bytes.get(i + 1) == Some(&b'e')
+        && i + 2 == bytes.len()
+}
+
+/// Endings read the same way wherever they are.
+const ENDINGS: &[(&str, &str)] = &[("tion", "ション"), ("sion", "ジョン"), ("ture", "チャー")];
+
+/// Spellings of vowels, with the vowel of the kana and what follows it.
+const VOWELS: &[(&str, usize, &str)] = &[
+    ("igh", 0, "イ"),
+    ("ee", 1, "ー"),
+    ("ea", 1, "ー"),
+    ("oo", 2, "ー"),
+    ("ou", 0, "ウ"),
+    ("ow", 4, "ウ"),
+    ("ai", 3, "イ"),
+    ("ay", 3, "イ"),
+    ("oa", 4, "ウ"),
+    ("ar", 0, "ー"),
+    ("er", 0, "ー"),
+    ("ir", 0, "ー"),
+    ("ur", 0, "ー"),
+    ("or", 4, "ー"),
+    ("a", 0, ""),
+    ("i", 1, ""),
+    ("u", 2, ""),
+    ("e", 3, ""),
+    ("o", 4, ""),
+];
+
+fn is_vowel(c: u8) -> bool {
+    b"aeiou".contains(&c)
+}
+
+/// Reads a lowercase word by its spelling, which is only a rough guess.
+fn transliterate(word: &str) -> String {
+    let bytes = word.as_bytes();
+    let at = |i: usize| bytes.get(i).copied();
+    let mut kana = String::new();
+    let mut i = 0;
+    'outer: while i < bytes.len() {
+        for (ending, reading) in ENDINGS {
+            if word[i..].starts_with(ending) {
+                kana.push_str(reading);
+                i += ending.len();
+                continue 'outer;
+            }
+        }
+
+        // As in "knife".
+        if i == 0 && word.starts_with("kn") {
+            i += 1;
+            continue;
+        }
+        if word[i..].starts_with("ck") {
+            kana.push_str("ック");
+            i += 2;
+            continue;
+        }
+        // A doubled consonant after a vowel is a geminate, as in "apple".
+        if i > 0
+            && at(i) == at(i + 1)
+            && matches!(at(i), Some(b'b' | b'd' | b'g' | b'k' | b'p' | b't'))
+            && is_vowel(bytes[i - 1])
+        {
+            kana.push('ッ');
+            i += 1;
+            continue;
+        }
+
+        let mut consonant = "";
+        for digraph in ["ch", "sh", "th", "ph", "wh", "qu"] {
+            if word[i..].starts_with(digraph) {
+                consonant = &word[i..i + 2];
+                break;
+            }
+        }
+        if consonant.is_empty() && !is_vowel(bytes[i]) {
+            // "y" is a vowel unless a vowel follows it.
+            if bytes[i] != b'y' || at(i + 1).map_or(false, is_vowel) {
+                consonant = &word[i..i + 1];
+            }
+        }
+        // The second of "ll" or "ss" is silent.
+        if consonant.len() == 1 && at(i + 1) == Some(bytes[i]) {
+            i += 1;
+        }
+        i += consonant.len();
+        if consonant == "qu" {
+            consonant = "q";
+        }
+        if consonant == "c" && matches!(at(i), Some(b'e' | b'i' | b'y')) {
+            consonant = "s";
+        }
+        if consonant == "g" && at(i) == Some(b'e') && i + 1 == bytes.len() {
+            // As in "page".
+            consonant = "j";
+        }
+        // Between vowels, as in "music" or "user".
+        if consonant == "s"
+            && i >= 2
+            && is_vowel(bytes[i - 2])
+            && at(i).map_or(false, is_vowel)
+            && !(at(i) == Some(b'e') && i + 1 == bytes.len())
+        {
+            consonant = "z";
+        }
+        if consonant == "x" {
+            // A final one after a vowel is a geminate, as in "box".
+            let geminate = i == bytes.len() && i >= 2 && is_vowel(bytes[i - 2]);
+            kana.push_str(if geminate { "ック" } else { "ク" });
+            consonant = "s";
+            if !at(i).map_or(false, is_vowel) {
+                kana.push('ス');
+                continue;
+            }
+        }
+
+        // As in "blue" or "queue".
+        if let Some(spelling) = ["ue", "eue"]
+            .iter()
+            .find(|spelling| &word[i..] == **spelling)
+        {
+            kana.push_str(long_u(consonant));
+            i += spelling.len();
+            continue;
+        }
+
+        // A final "e" after a consonant is silent, as in "make".
+        let silent_e = at(i) == Some(b'e') && i + 1 == bytes.len() && i > 2;
+        let vowel = VOWELS.iter().find(|(spelling, _, _)| {
+            word[i..].starts_with(spelling)
+                // "r" followed by a vowel starts the next syllable.
+                && !(spelling.ends_with('r') && at(i + 2).map_or(false, is_vowel))
+        });
+        match vowel {
+            Some((spelling, vowel, rest)) if !silent_e => {
+                i += spelling.len();
+                let single = spelling.len() == 1;
+                let before_tion = ["tion", "sion"].iter().any(|e| word[i..].starts_with(e));
+                if single && *vowel == 0 && (&word[i..] == "ge" || before_tion) {
+                    // As in "page" or "station".
+                    kana.push_str(row(consonant)[3]);
+                    kana.push('ー');
+                } else if single && *vowel == 4 && before_tion {
+                    // As in "motion".
+                    kana.push_str(row(consonant)[4]);
+                    kana.push('ー');
+                } else if single
+                    && (before_silent_e(bytes, i)
+                        // "u" before one consonant and a vowel, as in "music".
+                        || *vowel == 2
+                            && !consonant.is_empty()
+                            && at(i).map_or(false, |c| !is_vowel(c) && !b"rwxy".contains(&c))
+                            && at(i + 1).map_or(false, is_vowel))
+                {
+                    kana.push_str(&long(consonant, *vowel));
+                } else {
+                    kana.push_str(row(consonant)[*vowel]);
+                    kana.push_str(rest);
+                    if single && *vowel == 4 && i == bytes.len() && word.ends_with("llo") {
+                        // As in "hello".
+                        kana.push('ー');
+                    } else if single
+                        && i + 1 == bytes.len()
+                        && matches!(at(i), Some(b'c' | b'd' | b'g' | b'k' | b'p' | b't'))
+                    {
+                        // A final stop after a short vowel is a geminate, as
+                        // in "hit" or "music".
+                        kana.push('ッ');
+                    }
+                }
+            }
+            _ if at(i) == Some(b'y') && consonant != "y" => {
+                // As in "happy", "my" or "style".
+                if before_silent_e(bytes, i + 1) {
+                    kana.push_str(&long(consonant, 1));
+                } else {
+                    kana.push_str(row(consonant)[1]);
+                    kana.push_str(if i + 1 == bytes.len() { "ー" } else { "" });
+                }
+                i += 1;
+            }
+            _ => {
+                let next = at(i + usize::from(silent_e));
+                if consonant == "m" && matches!(next, Some(b'b' | b'p')) {
+                    kana.push('ン');
+                } else {
+                    kana.push_str(lone(consonant));
+                }
+                if silent_e {
+                    i += 1;
+                }
+            }
+        }
+    }
+    kana
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_english_to_kana() {
+        assert_eq!(
+            english_to_kana("GitHubでPRを出した"),
+            "ギットハブでピーアールを出した"
+        );
+        assert_eq!(english_to_kana("APIが遅い"), "エーピーアイが遅い");
+        assert_eq!(
+            english_to_kana("HTMLParser"),
+            "エイチティーエムエルパーサー"
+        );
+        assert_eq!(english_to_kana("日本語だけ"), "日本語だけ");
+        assert_eq!(english_to_kana("Hello, world!"), "ハロー, ワールド!");
+        assert_eq!(
+            english_to_kana("https://example.com/docs を見て"),
+            "https://example.com/docs を見て"
+        );
+        assert_eq!(english_to_kana("v1.2.3とsnake_case"), "v1.2.3とsnake_case");
+    }
+
+    #[test]
+    fn test_transliterate() {
+        assert_eq!(transliterate("apple"), "アップル");
+        assert_eq!(transliterate("happy"), "ハッピー");
+        assert_eq!(transliterate("station"), "ステーション");
+        assert_eq!(transliterate("server"), "サーバー");
+        assert_eq!(transliterate("class"), "クラス");
+        assert_eq!(transliterate("box"), "ボックス");
+    }
+
+    #[test]
+    fn test_transliterate_silent_e() {
+        assert_eq!(transliterate("make"), "メイク");
+        assert_eq!(transliterate("time"), "タイム");
+        assert_eq!(transliterate("note"), "ノート");
+        assert_eq!(transliterate("code"), "コード");
+        assert_eq!(transliterate("cute"), "キュート");
+        assert_eq!(transliterate("file"), "ファイル");
+        assert_eq!(transliterate("style"), "スタイル");
+        assert_eq!(transliterate("page"), "ページ");
+    }
+
+    #[test]
+    fn test_transliterate_long_vowels() {
+        assert_eq!(transliterate("music"), "ミュージック");
+        assert_eq!(transliterate("image"), "イメージ");
+        assert_eq!(transliterate("hello"), "ヘロー");
+        assert_eq!(transliterate("queue"), "キュー");
+        assert_eq!(transliterate("blue"), "ブルー");
+        assert_eq!(transliterate("motion"), "モーション");
+    }
+}
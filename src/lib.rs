@@ -1,8 +1,36 @@
+mod api;
+pub mod audio;
+mod cache;
+mod directive;
+mod emoji;
+mod guild_settings;
+mod invalidation;
+mod kana;
+mod metrics;
+mod normalize;
 mod option_builder;
 mod option_storage;
+mod pipeline;
+mod queue;
+pub mod render;
+mod session;
+mod simulate;
+mod spam;
 pub mod tts;
+mod usage;
 
+pub use self::api::{Api, Queues};
+pub use self::cache::{DEFAULT_CACHE_CAPACITY, DEFAULT_CACHE_TTL};
+pub use self::emoji::EmojiMode;
+pub use self::guild_settings::{
+    ng_word_pattern, GuildSettings, GuildSettingsStorage, ReplyMode, Rule,
+};
 pub use self::option_storage::OptionStorage;
+pub use self::pipeline::{Chat, ChatMessage, Outcome, Pipeline, PipelineConfig, Voice};
+pub use self::queue::{SpeechQueue, Utterance};
+pub use self::session::{Session, SessionRegistry, SessionStorage};
+pub use self::simulate::simulate;
+pub use self::usage::{Period, Quota, QuotaExceeded, Scope, Totals, Usage, UsageStorage};
 pub use option_builder::*;
 
 #[macro_use]
@@ -1,33 +1,35 @@
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::env;
-use std::fmt;
-use std::fs::File;
+use std::fs;
 use std::io::{self, Write};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
-use lingua::{Language, LanguageDetector, LanguageDetectorBuilder};
-use once_cell::sync::{Lazy, OnceCell};
+use once_cell::sync::OnceCell;
 use parking_lot::RwLock;
 
+use serenity::model::event::MessageUpdateEvent;
 use serenity::model::id::ChannelId;
 use serenity::model::id::GuildId;
+use serenity::model::id::MessageId;
+use serenity::model::id::RoleId;
+use serenity::model::id::UserId;
 use serenity::model::prelude::VoiceState;
 use songbird::{
     create_player,
-    driver::Bitrate,
-    input::{
-        self,
-        cached::{Compressed, Memory},
-        Input,
-    },
-    Call, Event, EventContext, EventHandler as VoiceEventHandler, SerenityInit, TrackEvent,
+    input::{self, cached::Memory},
+    tracks::{Track, TrackHandle},
+    Call, CoreEvent, Event, EventContext, EventHandler as VoiceEventHandler, SerenityInit,
+    Songbird, TrackEvent,
 };
 
 // Import the `Context` to handle commands.
+use serenity::client::bridge::gateway::ShardManager;
 use serenity::client::Context;
 
 use serenity::{
@@ -35,105 +37,683 @@ use serenity::{
     client::{Client, EventHandler},
     framework::{
         standard::{
-            macros::{command, group},
-            Args, CommandResult,
+            macros::{check, command, group, hook},
+            Args, CommandOptions, CommandResult, DispatchError, Reason,
         },
         StandardFramework,
     },
-    model::{channel::Message, gateway::Ready},
-    utils::MessageBuilder,
-    Result as SerenityResult,
+    http::Http,
+    model::{
+        channel::{Embed, Message},
+        gateway::Ready,
+        guild::Guild,
+        user::User,
+    },
+    utils::{content_safe, ContentSafeOptions, MessageBuilder},
+    CacheAndHttp, Result as SerenityResult,
 };
-use strum::IntoEnumIterator;
-use uuid::Uuid;
-
+use sqlx::mysql::MySqlPool;
+use strum::{EnumString, IntoEnumIterator};
+use tokio::io::BufReader;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+
+use ttsbot::audio::TempFile;
+use ttsbot::render::{self, Names};
 use ttsbot::tts;
-use ttsbot::OptionStorage;
-use ttsbot::{build_voice_text_options, build_voice_vox_options};
+use ttsbot::tts::voice_text::VoiceTextFormat;
+use ttsbot::{build_options, build_voice_text_options, build_voice_vox_options};
+use ttsbot::{ng_word_pattern, Api, GuildSettings, GuildSettingsStorage, OptionStorage, Rule};
+use ttsbot::{Chat, ChatMessage, Outcome, Pipeline, PipelineConfig, Voice};
+use ttsbot::{Period, Quota, QuotaExceeded, ReplyMode, Scope, UsageStorage};
+use ttsbot::{Queues, SpeechQueue};
+use ttsbot::{Session, SessionRegistry, SessionStorage};
+use ttsbot::{DEFAULT_CACHE_CAPACITY, DEFAULT_CACHE_TTL};
+
+static PIPELINE: OnceCell<Pipeline> = OnceCell::new();
+static BOT_JOINING_CHANNEL: OnceCell<RwLock<SessionRegistry>> = OnceCell::new();
+static BOTS: OnceCell<Vec<Bot>> = OnceCell::new();
+static SESSION_STORAGE: OnceCell<SessionStorage> = OnceCell::new();
+static QUEUES: OnceCell<Queues> = OnceCell::new();
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Plays audio in the voice channel a bot account is in.
+struct CallVoice {
+    handler_lock: Arc<tokio::sync::Mutex<Call>>,
+    playing: parking_lot::Mutex<Option<TrackHandle>>,
+}
 
-static LANGUAGE_DETECTOR: OnceCell<LanguageDetector> = OnceCell::new();
-static TTS_CLIENT: OnceCell<tts::Client> = OnceCell::new();
-// static OPTION_STORAGE: Lazy<RwLock<OptionStorage>> =
-//     Lazy::new(|| RwLock::new(OptionStorage::new()));
-static OPTION_STORAGE: OnceCell<RwLock<OptionStorage>> = OnceCell::new();
-static BOT_JOINING_CHANNEL: OnceCell<RwLock<HashMap<GuildId, ChannelId>>> = OnceCell::new();
+impl CallVoice {
+    fn new(handler_lock: Arc<tokio::sync::Mutex<Call>>) -> Self {
+        Self {
+            handler_lock,
+            playing: parking_lot::Mutex::new(None),
+        }
+    }
+}
 
-async fn play_voice(
-    handler_lock: Arc<tokio::sync::Mutex<Call>>,
-    text: impl fmt::Display,
-    options: &tts::Options,
-) -> anyhow::Result<()> {
-    let detector = LANGUAGE_DETECTOR
-        .get()
-        .expect("Language detector is not initialized");
-    if let Some(lang @ Language::Japanese) = detector.detect_language_of(text.to_string()) {
-        let sound_src = {
-            let sound_data = TTS_CLIENT
-                .get()
-                .expect("TTS_CLIENT is not initialized")
-                .request(text, options)
-                .await?;
-            let temp_dir = env::temp_dir();
-            // TODO: format
-            let file_path = temp_dir.join(format!("ttsbot_{}.wav", Uuid::new_v4()));
-            let mut file = File::create(&file_path)?;
-            file.write_all(&sound_data)?;
-            file.flush()?;
-            Memory::new(input::ffmpeg(&file_path).await?)?
-        };
+/// A track decoded and ready to be played.
+struct PreparedTrack {
+    audio: Track,
+    track: TrackHandle,
+    ended: Arc<Notify>,
+    // ffmpeg may still be reading it.
+    _file: TempFile,
+}
+
+#[async_trait]
+impl Voice for CallVoice {
+    type Prepared = PreparedTrack;
+
+    async fn prepare(&self, audio: Vec<u8>) -> anyhow::Result<PreparedTrack> {
+        let file = TempFile::create(&audio)?;
+        let sound_src = Memory::new(input::ffmpeg(file.path()).await?)?;
         let _ = sound_src.raw.spawn_loader();
-        let (mut audio, _) = create_player(sound_src.new_handle().try_into()?);
+        let (mut audio, track) = create_player(sound_src.new_handle().try_into()?);
         audio.set_volume(0.1);
-        let mut handler = handler_lock.lock().await;
-        handler.play(audio);
+
+        let ended = Arc::new(Notify::new());
+        track.add_event(Event::Track(TrackEvent::End), TrackEnd(ended.clone()))?;
+        Ok(PreparedTrack {
+            audio,
+            track,
+            ended,
+            _file: file,
+        })
+    }
+
+    async fn play(&self, prepared: PreparedTrack) -> anyhow::Result<()> {
+        *self.playing.lock() = Some(prepared.track);
+        self.handler_lock.lock().await.play(prepared.audio);
+        prepared.ended.notified().await;
+        *self.playing.lock() = None;
+        Ok(())
+    }
+
+    fn stop(&self) {
+        if let Some(track) = self.playing.lock().take() {
+            let _ = track.stop();
+        }
     }
-    Ok(())
 }
 
-struct Handler;
+struct TrackEnd(Arc<Notify>);
 
 #[async_trait]
-impl EventHandler for Handler {
-    async fn ready(&self, _: Context, ready: Ready) {
-        println!("{} is connected!", ready.user.name);
+impl VoiceEventHandler for TrackEnd {
+    async fn act(&self, _: &EventContext<'_>) -> Option<Event> {
+        self.0.notify_one();
+        None
     }
+}
 
-    async fn message(&self, ctx: Context, msg: Message) {
-        if msg.content.starts_with(".") || msg.is_own(&ctx.cache).await {
+/// The guild a message was posted in, as seen by one of the bot accounts.
+struct GuildChat<'a> {
+    bot_id: UserId,
+    guild: &'a Guild,
+}
+
+#[async_trait]
+impl Chat for GuildChat<'_> {
+    fn bot_id(&self) -> UserId {
+        self.bot_id
+    }
+
+    async fn voice_channel_of(&self, _: GuildId, user_id: UserId) -> Option<ChannelId> {
+        self.guild
+            .voice_states
+            .get(&user_id)
+            .and_then(|voice_state| voice_state.channel_id)
+    }
+
+    fn reading_channel(&self, guild_id: GuildId) -> Option<ChannelId> {
+        BOT_JOINING_CHANNEL
+            .get()
+            .unwrap()
+            .read()
+            .find_by_bot(guild_id, self.bot_id)
+            .map(|session| session.voice_channel_id)
+    }
+
+    fn text_channel(&self, guild_id: GuildId) -> Option<ChannelId> {
+        BOT_JOINING_CHANNEL
+            .get()
+            .unwrap()
+            .read()
+            .find_by_bot(guild_id, self.bot_id)
+            .map(|session| session.text_channel_id)
+    }
+}
+
+impl Names for GuildChat<'_> {
+    fn channel_name(&self, channel_id: ChannelId) -> Option<String> {
+        self.guild
+            .channels
+            .get(&channel_id)
+            .or_else(|| self.guild.threads.iter().find(|t| t.id == channel_id))
+            .map(|channel| channel.name.clone())
+    }
+
+    fn role_name(&self, role_id: RoleId) -> Option<String> {
+        self.guild.roles.get(&role_id).map(|role| role.name.clone())
+    }
+}
+
+fn find_queue(guild_id: GuildId, bot_id: UserId) -> Option<Arc<SpeechQueue>> {
+    QUEUES
+        .get()
+        .unwrap()
+        .read()
+        .get(&(guild_id, bot_id))
+        .cloned()
+}
+
+/// One of the bot accounts driven by this process.
+struct Bot {
+    id: UserId,
+    songbird: Arc<Songbird>,
+    cache_and_http: Arc<CacheAndHttp>,
+}
+
+impl Bot {
+    fn get(bot_id: UserId) -> Option<&'static Bot> {
+        BOTS.get().unwrap().iter().find(|bot| bot.id == bot_id)
+    }
+}
+
+/// Finds the session of the voice channel the user is in, or the only session
+/// in the guild when the user is not in any of them.
+fn find_session(guild: &Guild, user_id: &UserId) -> Option<(Session, &'static Bot)> {
+    let sessions = BOT_JOINING_CHANNEL.get().unwrap().read();
+    let session = guild
+        .voice_states
+        .get(user_id)
+        .and_then(|voice_state| voice_state.channel_id)
+        .and_then(|channel_id| sessions.find_by_channel(guild.id, channel_id))
+        .or_else(|| match sessions.sessions_in(guild.id) {
+            [session] => Some(*session),
+            _ => None,
+        })?;
+    Some((session, Bot::get(session.bot_id)?))
+}
+
+/// Records the session both in memory and in the database.
+async fn bind_session(guild_id: GuildId, session: Session) {
+    BOT_JOINING_CHANNEL
+        .get()
+        .unwrap()
+        .write()
+        .bind(guild_id, session);
+    if let Some(handler_lock) = Bot::get(session.bot_id).and_then(|bot| bot.songbird.get(guild_id))
+    {
+        if find_queue(guild_id, session.bot_id).is_none() {
+            let queue = Arc::new(SpeechQueue::new());
+            {
+                let mut handler = handler_lock.lock().await;
+                for event in [
+                    CoreEvent::DriverConnect,
+                    CoreEvent::DriverReconnect,
+                    CoreEvent::DriverDisconnect,
+                ] {
+                    handler.add_global_event(
+                        Event::Core(event),
+                        DriverEvents {
+                            guild_id,
+                            bot_id: session.bot_id,
+                        },
+                    );
+                }
+            }
+            QUEUES
+                .get()
+                .unwrap()
+                .write()
+                .insert((guild_id, session.bot_id), queue.clone());
+            tokio::spawn(
+                PIPELINE
+                    .get()
+                    .unwrap()
+                    .speak(queue, CallVoice::new(handler_lock)),
+            );
+        }
+    }
+    if let Err(why) = SESSION_STORAGE
+        .get()
+        .unwrap()
+        .save(&guild_id, &session)
+        .await
+    {
+        println!("Failed to save session: {:?}", why);
+    }
+}
+
+/// Forgets the session. While shutting down it is kept in the database so that
+/// it is restored on the next start.
+async fn unbind_session(guild_id: GuildId, bot_id: UserId) {
+    BOT_JOINING_CHANNEL
+        .get()
+        .unwrap()
+        .write()
+        .unbind(guild_id, bot_id);
+    if let Some(queue) = QUEUES.get().unwrap().write().remove(&(guild_id, bot_id)) {
+        queue.close();
+    }
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+        return;
+    }
+    if let Err(why) = SESSION_STORAGE
+        .get()
+        .unwrap()
+        .delete(&guild_id, &bot_id)
+        .await
+    {
+        println!("Failed to delete session: {:?}", why);
+    }
+}
+
+/// Keeps the queue of a session in step with its voice connection, rejoining
+/// the channel when the connection is lost.
+struct DriverEvents {
+    guild_id: GuildId,
+    bot_id: UserId,
+}
+
+#[async_trait]
+impl VoiceEventHandler for DriverEvents {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        match ctx {
+            EventContext::DriverConnect(_) | EventContext::DriverReconnect(_) => {
+                println!("Voice connection in {} is up", self.guild_id);
+                if let Some(queue) = find_queue(self.guild_id, self.bot_id) {
+                    queue.resume();
+                }
+            }
+            EventContext::DriverDisconnect(data) => {
+                println!(
+                    "Voice connection in {} was lost: {:?} ({:?})",
+                    self.guild_id, data.kind, data.reason
+                );
+                if let Some(queue) = find_queue(self.guild_id, self.bot_id) {
+                    queue.pause();
+                }
+                tokio::spawn(rejoin(self.guild_id, self.bot_id));
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+const REJOIN_ATTEMPTS: u32 = 5;
+
+/// Rejoins the voice channel of the session unless the connection recovers by
+/// itself or the session ends in the meantime.
+async fn rejoin(guild_id: GuildId, bot_id: UserId) {
+    for attempt in 0..REJOIN_ATTEMPTS {
+        tokio::time::sleep(Duration::from_secs(2u64.pow(attempt + 1))).await;
+        if SHUTTING_DOWN.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let session = match BOT_JOINING_CHANNEL
+            .get()
+            .unwrap()
+            .read()
+            .find_by_bot(guild_id, bot_id)
+        {
+            Some(session) => session,
+            None => return,
+        };
+        let bot = match Bot::get(bot_id) {
+            Some(bot) => bot,
+            None => return,
+        };
+        let handler_lock = match bot.songbird.get(guild_id) {
+            Some(handler_lock) => handler_lock,
+            None => return,
+        };
+        if handler_lock.lock().await.current_connection().is_some() {
+            if let Some(queue) = find_queue(guild_id, bot_id) {
+                queue.resume();
+            }
             return;
         }
 
-        let guild = msg.guild(&ctx.cache).await.unwrap();
-        let guild_id = guild.id;
+        println!(
+            "Rejoining {} in {} (attempt {})",
+            session.voice_channel_id,
+            guild_id,
+            attempt + 1
+        );
+        let (_handler, result) = bot.songbird.join(guild_id, session.voice_channel_id).await;
+        match result {
+            Ok(()) => {
+                if let Some(queue) = find_queue(guild_id, bot_id) {
+                    queue.resume();
+                }
+                return;
+            }
+            Err(why) => println!("Failed to rejoin {}: {:?}", session.voice_channel_id, why),
+        }
+    }
+    println!("Gave up rejoining in {}", guild_id);
+}
+
+/// What to do when a message that has been read or queued is edited.
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+enum EditAction {
+    Ignore,
+    /// Read the edited text instead if the message hasn't been read yet.
+    Update,
+    /// Read the edited text, again if the message has already been read.
+    Reread,
+}
 
+struct Handler {
+    bot_id: UserId,
+    on_edit: EditAction,
+}
+
+/// A message, or an edit of one, as received from Discord.
+struct Received<'a> {
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    author: &'a User,
+    webhook: bool,
+    content: &'a str,
+    /// Read after the content, such as the attachments and stickers.
+    extras: Vec<String>,
+    /// The message this replies to, if any.
+    reply_to: Option<&'a Message>,
+    embeds: &'a [Embed],
+    edited: bool,
+}
+
+impl<'a> Received<'a> {
+    fn new(msg: &'a Message, edited: bool) -> Option<Self> {
+        Some(Self {
+            guild_id: msg.guild_id?,
+            channel_id: msg.channel_id,
+            message_id: msg.id,
+            author: &msg.author,
+            webhook: msg.webhook_id.is_some(),
+            content: &msg.content,
+            extras: msg
+                .stickers
+                .iter()
+                .map(|sticker| render::sticker(&sticker.name))
+                .chain(
+                    msg.attachments
+                        .iter()
+                        .map(|attachment| render::attachment(&attachment.filename)),
+                )
+                .collect(),
+            reply_to: msg.referenced_message.as_deref(),
+            embeds: &msg.embeds,
+            edited,
+        })
+    }
+}
+
+/// Messages cached per channel so that their edits can be read.
+const MESSAGE_CACHE_SIZE: usize = 100;
+
+/// Characters of the original message read with a reply.
+const REPLY_EXCERPT_LENGTH: usize = 20;
+
+/// The content with the markup and the mentions made readable.
+async fn readable(ctx: &Context, chat: &GuildChat<'_>, content: &str) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let rendered = render::render(content, chat, now);
+    let options = ContentSafeOptions::default().display_as_member_from(chat.guild.id);
+    content_safe(&ctx.cache, rendered, &options).await
+}
+
+/// The text of an embed, which is all there is to read in the messages of
+/// some bots.
+fn embed_text(embed: &Embed) -> String {
+    let mut parts = Vec::new();
+    parts.extend(embed.author.as_ref().map(|author| author.name.clone()));
+    parts.extend(embed.title.clone());
+    parts.extend(embed.description.clone());
+    for field in &embed.fields {
+        parts.push(format!("{} {}", field.name, field.value));
+    }
+    parts.join("、")
+}
+
+impl Handler {
+    /// Runs the message through the pipeline, returning the queue of this bot
+    /// account with what becomes of the message.
+    async fn read(
+        &self,
+        ctx: &Context,
+        received: Received<'_>,
+    ) -> Option<(Arc<SpeechQueue>, Outcome)> {
+        if SHUTTING_DOWN.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        let guild = ctx.cache.guild(received.guild_id).await?;
+        let queue = find_queue(guild.id, self.bot_id)?;
+
+        // Every bot account receives the message, but only the one reading the
+        // author's voice channel speaks it.
+        let chat = GuildChat {
+            bot_id: self.bot_id,
+            guild: &guild,
+        };
+        let settings = match PIPELINE.get().unwrap().guild_settings().get(guild.id).await {
+            Ok(settings) => settings,
+            Err(why) => {
+                println!("Failed to load guild settings: {:?}", why);
+                return None;
+            }
+        };
+
+        let mut reply = None;
+        if let Some(original) = received
+            .reply_to
+            .filter(|_| settings.reply != ReplyMode::Off)
         {
-            let authors_voice_channel_id = guild
-                .voice_states
-                .get(&msg.author.id)
-                .and_then(|voice_state| voice_state.channel_id);
+            let name = match guild.members.get(&original.author.id) {
+                Some(member) => member.display_name().to_string(),
+                None => original.author.name.clone(),
+            };
+            let mut to = format!("{}さんへの返信", name);
+            if settings.reply == ReplyMode::Excerpt {
+                let excerpt: String = readable(ctx, &chat, &original.content)
+                    .await
+                    .chars()
+                    .take(REPLY_EXCERPT_LENGTH)
+                    .collect();
+                if !excerpt.trim().is_empty() {
+                    to.push_str(&format!("、「{}」", excerpt));
+                }
+            }
+            reply = Some(to);
+        }
+        let text = readable(ctx, &chat, received.content).await;
 
-            let lock = BOT_JOINING_CHANNEL.get().unwrap().read();
-            let bots_voice_channel_id = lock.get(&guild_id).cloned();
+        let mut embeds = Vec::new();
+        for embed in received.embeds {
+            embeds.push(readable(ctx, &chat, &embed_text(embed)).await);
+        }
+        let chat_message = ChatMessage {
+            id: received.message_id,
+            guild_id: guild.id,
+            channel_id: received.channel_id,
+            author_id: received.author.id,
+            author_is_bot: received.author.bot,
+            author_roles: guild
+                .members
+                .get(&received.author.id)
+                .map(|member| member.roles.clone())
+                .unwrap_or_default(),
+            is_webhook: received.webhook,
+            content: received.content.to_string(),
+            text,
+            reply,
+            extras: received.extras,
+            embeds,
+            edited: received.edited,
+        };
+        match PIPELINE.get().unwrap().process(&chat, &chat_message).await {
+            Ok(outcome) => Some((queue, outcome)),
+            Err(why) => {
+                match why.downcast_ref::<QuotaExceeded>() {
+                    Some(exceeded) => {
+                        if exceeded.notify {
+                            // Tells who it is without pinging them.
+                            check_msg(
+                                received
+                                    .channel_id
+                                    .send_message(&ctx.http, |m| {
+                                        m.content(exceeded).allowed_mentions(|am| am.empty_parse())
+                                    })
+                                    .await,
+                            );
+                        }
+                    }
+                    None => println!("Failed to process message: {:?}", why),
+                }
+                None
+            }
+        }
+    }
+}
 
-            if authors_voice_channel_id != bots_voice_channel_id {
+#[async_trait]
+impl EventHandler for Handler {
+    async fn ready(&self, _: Context, ready: Ready) {
+        println!("{} is connected!", ready.user.name);
+    }
+
+    // Sessions are restored here rather than in `ready` because voice states
+    // are only known once the guilds have been cached.
+    async fn cache_ready(&self, ctx: Context, _: Vec<GuildId>) {
+        let sessions = match SESSION_STORAGE.get().unwrap().load(&self.bot_id).await {
+            Ok(sessions) => sessions,
+            Err(why) => {
+                println!("Failed to load sessions: {:?}", why);
                 return;
             }
-        }
+        };
 
         let manager = songbird::get(&ctx)
             .await
             .expect("Songbird Voice client placed in at initialisation.")
             .clone();
 
-        let options = {
-            let storage = OPTION_STORAGE.get().unwrap().read();
-            storage.get(&msg.author.id)
+        for (guild_id, session) in sessions {
+            let has_humans = match ctx.cache.guild_channel(session.voice_channel_id).await {
+                Some(channel) => channel
+                    .members(&ctx.cache)
+                    .await
+                    .map(|members| members.iter().any(|m| !m.user.bot))
+                    .unwrap_or(false),
+                None => false,
+            };
+            if !has_humans {
+                unbind_session(guild_id, self.bot_id).await;
+                continue;
+            }
+
+            let (_handler, result) = manager.join(guild_id, session.voice_channel_id).await;
+            match result {
+                Ok(()) => {
+                    println!("Rejoined {} in {}", session.voice_channel_id, guild_id);
+                    bind_session(guild_id, session).await;
+                }
+                Err(why) => {
+                    println!("Failed to rejoin {}: {:?}", session.voice_channel_id, why);
+                    unbind_session(guild_id, self.bot_id).await;
+                }
+            }
+        }
+    }
+
+    async fn message(&self, ctx: Context, msg: Message) {
+        let received = match Received::new(&msg, false) {
+            Some(received) => received,
+            None => return,
         };
+        if let Some((queue, Outcome::Read(utterances))) = self.read(&ctx, received).await {
+            queue.extend(utterances);
+        }
+    }
 
-        if let Some(handler_lock) = manager.get(guild_id) {
-            play_voice(handler_lock, msg.content_safe(&ctx.cache).await, &options)
-                .await
-                .ok();
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _: Option<Message>,
+        new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        if self.on_edit == EditAction::Ignore {
+            return;
+        }
+        // Updates without content are embeds being added, e.g. link previews.
+        if event.content.is_none() {
+            return;
+        }
+        // The event lacks what the message replies to and whether it was posted
+        // through a webhook, so a message that has fallen out of the cache is
+        // left as it was read.
+        let received = match new.as_ref().and_then(|msg| Received::new(msg, true)) {
+            Some(received) => received,
+            None => return,
+        };
+        if self.on_edit == EditAction::Update
+            && !find_queue(received.guild_id, self.bot_id)
+                .map_or(false, |queue| queue.is_waiting(event.id))
+        {
+            return;
+        }
+        match self.read(&ctx, received).await {
+            Some((queue, Outcome::Read(utterances))) => {
+                if !queue.replace(event.id, utterances.clone())
+                    && self.on_edit == EditAction::Reread
+                {
+                    queue.cancel(event.id);
+                    queue.extend(utterances);
+                }
+            }
+            // Edited into something not to be read.
+            Some((queue, Outcome::Ignored)) => {
+                queue.cancel(event.id);
+            }
+            Some((_, Outcome::Throttled)) | None => {}
+        }
+    }
+
+    async fn message_delete(
+        &self,
+        _: Context,
+        _: ChannelId,
+        message_id: MessageId,
+        guild_id: Option<GuildId>,
+    ) {
+        if let Some(queue) = guild_id.and_then(|guild_id| find_queue(guild_id, self.bot_id)) {
+            queue.cancel(message_id);
+        }
+    }
+
+    async fn message_delete_bulk(
+        &self,
+        _: Context,
+        _: ChannelId,
+        message_ids: Vec<MessageId>,
+        guild_id: Option<GuildId>,
+    ) {
+        if let Some(queue) = guild_id.and_then(|guild_id| find_queue(guild_id, self.bot_id)) {
+            for message_id in message_ids {
+                queue.cancel(message_id);
+            }
         }
     }
 
@@ -142,13 +722,28 @@ impl EventHandler for Handler {
         ctx: Context,
         guild_id: Option<GuildId>,
         old_state: Option<VoiceState>,
-        _: VoiceState,
+        new_state: VoiceState,
     ) {
+        let guild_id = match guild_id {
+            Some(guild_id) => guild_id,
+            None => return,
+        };
+
+        // Forget the binding when the bot itself was disconnected, e.g. by a
+        // moderator.
+        if new_state.user_id == self.bot_id && new_state.channel_id.is_none() {
+            unbind_session(guild_id, self.bot_id).await;
+            return;
+        }
+
         if let Some(old_state) = old_state {
-            let guild_id = guild_id.unwrap();
-            let lock = BOT_JOINING_CHANNEL.get().unwrap().read();
-            let bots_voice_channel_id = lock.get(&guild_id).cloned();
-            if bots_voice_channel_id != old_state.channel_id {
+            let bots_voice_channel_id = BOT_JOINING_CHANNEL
+                .get()
+                .unwrap()
+                .read()
+                .find_by_bot(guild_id, self.bot_id)
+                .map(|session| session.voice_channel_id);
+            if bots_voice_channel_id.is_none() || bots_voice_channel_id != old_state.channel_id {
                 return;
             }
 
@@ -164,30 +759,410 @@ impl EventHandler for Handler {
                     if has_handler {
                         manager.remove(guild_id).await.unwrap();
                     }
+                    unbind_session(guild_id, self.bot_id).await;
                 }
             }
         }
     }
 }
 
-#[group]
-#[commands(engine, join, leave, mute, ping, preset, set, stop, unmute)]
-struct General;
+#[group]
+#[commands(
+    config, dj, engine, ignore, join, leave, mute, ng, optin, optout, ping, preset, rule, set,
+    stop, unignore, unmute, usage
+)]
+struct General;
+
+// Lets through those with Manage Guild and those with one of the DJ roles.
+#[check]
+#[name = "Admin"]
+async fn admin_check(
+    ctx: &Context,
+    msg: &Message,
+    _: &mut Args,
+    _: &CommandOptions,
+) -> Result<(), Reason> {
+    if is_admin(ctx, msg).await {
+        Ok(())
+    } else {
+        Err(Reason::User(
+            "Requires Manage Guild or a DJ role".to_string(),
+        ))
+    }
+}
+
+// Lets through those in the voice channel of the bot, and admins, if the
+// guild restricts voice control. Lets through anyone otherwise.
+#[check]
+#[name = "VoiceControl"]
+async fn voice_control_check(
+    ctx: &Context,
+    msg: &Message,
+    _: &mut Args,
+    _: &CommandOptions,
+) -> Result<(), Reason> {
+    let guild = match msg.guild(&ctx.cache).await {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let settings = PIPELINE
+        .get()
+        .unwrap()
+        .guild_settings()
+        .get(guild.id)
+        .await
+        .map_err(|e| Reason::Log(format!("Error loading guild settings: {:?}", e)))?;
+    if !settings.restrict_voice_control
+        || in_bots_voice_channel(&guild, msg.author.id)
+        || is_admin(ctx, msg).await
+    {
+        Ok(())
+    } else {
+        Err(Reason::User(
+            "Only those in the voice channel of the bot can do that".to_string(),
+        ))
+    }
+}
+
+async fn is_admin(ctx: &Context, msg: &Message) -> bool {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => return false,
+    };
+    let member = match msg.member(ctx).await {
+        Ok(member) => member,
+        Err(_) => return false,
+    };
+    if member
+        .permissions(ctx)
+        .await
+        .map_or(false, |permissions| permissions.manage_guild())
+    {
+        return true;
+    }
+    match PIPELINE.get().unwrap().guild_settings().get(guild_id).await {
+        Ok(settings) => settings.is_dj(&member.roles),
+        Err(e) => {
+            println!("Error loading guild settings: {:?}", e);
+            false
+        }
+    }
+}
+
+fn in_bots_voice_channel(guild: &Guild, user_id: UserId) -> bool {
+    let sessions = BOT_JOINING_CHANNEL.get().unwrap().read();
+    guild
+        .voice_states
+        .get(&user_id)
+        .and_then(|voice_state| voice_state.channel_id)
+        .map_or(false, |channel_id| {
+            sessions.find_by_channel(guild.id, channel_id).is_some()
+        })
+}
+
+/// Tells the author why a command was refused.
+#[hook]
+async fn dispatch_error(ctx: &Context, msg: &Message, error: DispatchError) {
+    match error {
+        DispatchError::CheckFailed(_, Reason::User(reason)) => {
+            check_msg(msg.reply(ctx, reason).await)
+        }
+        DispatchError::LackingPermissions(_) => {
+            check_msg(msg.reply(ctx, "Requires Manage Guild").await)
+        }
+        _ => {}
+    }
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Opt {
+    #[clap(long, env)]
+    voicetext_api_key: String,
+
+    #[clap(long, env)]
+    voicevox_api_key: String,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Runs the bot
+    Run(RunOpt),
+    /// Reads lines from stdin as a user and writes the resulting audio files
+    Simulate(SimulateOpt),
+    /// Synthesizes the text into an audio file
+    Synth(SynthOpt),
+}
+
+#[derive(clap::Args, Debug)]
+struct RunOpt {
+    /// Bot tokens, comma-separated. Each bot account can read one voice
+    /// channel per guild. Commands are handled by the first one.
+    #[clap(
+        long = "discord-token",
+        env = "DISCORD_TOKEN",
+        use_value_delimiter = true,
+        required = true
+    )]
+    discord_tokens: Vec<String>,
+
+    #[clap(flatten)]
+    database: DatabaseOpt,
+
+    #[clap(flatten)]
+    quota: QuotaOpt,
+
+    #[clap(flatten)]
+    pipeline: PipelineOpt,
+
+    /// Seconds between polls for changes made by other bot processes
+    #[clap(long, env, default_value = "5")]
+    cache_sync_interval: u64,
+
+    /// Seconds to wait for queued messages to be read on shutdown
+    #[clap(long, env, default_value = "5")]
+    shutdown_timeout: u64,
+
+    /// What to do when a message is edited: ignore, update (read the edited
+    /// text if not read yet) or reread (read the edited text, even again)
+    #[clap(long, env, default_value = "ignore")]
+    on_edit: EditAction,
+
+    /// Address to serve the HTTP API and Prometheus metrics on, e.g.
+    /// 127.0.0.1:8080. Both are disabled unless this is given.
+    #[clap(long, env, requires = "http-token")]
+    http_addr: Option<SocketAddr>,
+
+    /// Bearer token the HTTP API requires
+    #[clap(long, env)]
+    http_token: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SimulateOpt {
+    /// The user whose options are used
+    #[clap(long)]
+    user_id: u64,
+
+    #[clap(long, default_value = "0")]
+    guild_id: u64,
+
+    /// Where the audio files are written
+    #[clap(long, default_value = ".")]
+    output_dir: PathBuf,
+
+    #[clap(flatten)]
+    database: DatabaseOpt,
+
+    #[clap(flatten)]
+    quota: QuotaOpt,
+
+    #[clap(flatten)]
+    pipeline: PipelineOpt,
+}
+
+#[derive(clap::Args, Debug)]
+struct SynthOpt {
+    /// Where the audio is written, or "-" for stdout
+    #[clap(short, long, default_value = "-")]
+    output: PathBuf,
+
+    /// wav, ogg or mp3. Guessed from the output path if not given
+    #[clap(long)]
+    format: Option<VoiceTextFormat>,
+
+    engine: tts::Engine,
+
+    text: String,
+
+    /// Options in the form "key=value", as in `.set`
+    options: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct DatabaseOpt {
+    #[clap(long, env)]
+    database_url: String,
+
+    /// Maximum number of users whose options, and of guilds whose settings,
+    /// are kept in memory
+    #[clap(long, env, default_value_t = DEFAULT_CACHE_CAPACITY)]
+    option_cache_capacity: usize,
+
+    /// Seconds until cached options and guild settings are read from the
+    /// database again
+    #[clap(long, env, default_value_t = DEFAULT_CACHE_TTL.as_secs())]
+    option_cache_ttl: u64,
+}
+
+impl DatabaseOpt {
+    /// Connects to the database, bringing its tables up to date first.
+    async fn connect(&self) -> anyhow::Result<MySqlPool> {
+        let pool = MySqlPool::connect(&self.database_url).await?;
+        sqlx::migrate!().run(&pool).await?;
+        Ok(pool)
+    }
+
+    async fn option_storage(&self, pool: &MySqlPool) -> anyhow::Result<OptionStorage> {
+        OptionStorage::new(
+            pool.clone(),
+            self.option_cache_capacity,
+            Duration::from_secs(self.option_cache_ttl),
+        )
+        .await
+    }
+
+    async fn guild_settings(&self, pool: &MySqlPool) -> anyhow::Result<GuildSettingsStorage> {
+        GuildSettingsStorage::new(
+            pool.clone(),
+            self.option_cache_capacity,
+            Duration::from_secs(self.option_cache_ttl),
+        )
+        .await
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct QuotaOpt {
+    /// Characters a guild can have read with paid engines per day
+    #[clap(long, env)]
+    guild_daily_quota: Option<u64>,
+
+    /// Characters a guild can have read with paid engines per month
+    #[clap(long, env)]
+    guild_monthly_quota: Option<u64>,
+
+    /// Characters a user can have read with paid engines per day, across
+    /// guilds
+    #[clap(long, env)]
+    user_daily_quota: Option<u64>,
+
+    /// Characters a user can have read with paid engines per month, across
+    /// guilds
+    #[clap(long, env)]
+    user_monthly_quota: Option<u64>,
+
+    /// Engine to read with once a quota is used up, instead of refusing. Its
+    /// usage doesn't count towards the quotas
+    #[clap(long, env)]
+    quota_fallback_engine: Option<tts::Engine>,
+
+    /// Options of the fallback engine in the form "key=value", as in `.set`
+    #[clap(
+        long = "quota-fallback-option",
+        env = "QUOTA_FALLBACK_OPTIONS",
+        use_value_delimiter = true
+    )]
+    quota_fallback_options: Vec<String>,
+}
+
+impl QuotaOpt {
+    fn quota(&self) -> anyhow::Result<Quota> {
+        let fallback = match &self.quota_fallback_engine {
+            Some(engine) => Some(build_options(engine, self.quota_fallback_options.iter())?),
+            None => None,
+        };
+        Ok(Quota {
+            guild_daily: self.guild_daily_quota,
+            guild_monthly: self.guild_monthly_quota,
+            user_daily: self.user_daily_quota,
+            user_monthly: self.user_monthly_quota,
+            fallback,
+        })
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct PipelineOpt {
+    /// Messages a user can have read per rate limit window
+    #[clap(long, env)]
+    rate_limit_messages: Option<usize>,
 
-#[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Opt {
+    /// Characters a user can have read per rate limit window
     #[clap(long, env)]
-    voicetext_api_key: String,
+    rate_limit_characters: Option<usize>,
 
+    /// Seconds of the rate limit window
+    #[clap(long, env, default_value = "10")]
+    rate_limit_window: u64,
+
+    /// Seconds a message identical to the previous one is not read for
+    #[clap(long, env, default_value = "30")]
+    repeat_window: u64,
+
+    /// Runs of the same character longer than this are shortened
+    #[clap(long, env, default_value = "3")]
+    max_repeated_characters: usize,
+
+    /// Messages longer than this are cut off with "以下略". Omitted or 0, they
+    /// are read to the end
     #[clap(long, env)]
-    voicevox_api_key: String,
+    max_length: Option<usize>,
 
+    /// Messages longer than this are read in chunks split at the ends of
+    /// sentences
+    #[clap(long, env, default_value = "100")]
+    chunk_length: usize,
+
+    /// Utterances of a session synthesized ahead of being read
+    #[clap(long, env, default_value = "2")]
+    prefetch_depth: usize,
+
+    /// Requests each TTS engine is sent at once, across sessions
+    #[clap(long, env, default_value = "4")]
+    synthesis_concurrency: usize,
+
+    /// Speak faster as messages pile up
     #[clap(long, env)]
-    discord_token: String,
+    adaptive_speed: bool,
+
+    /// How many times as fast adaptive speed can make speech
+    #[clap(long, env, default_value = "1.5")]
+    max_speed_up: f64,
 
+    /// Seconds after which queued messages are dropped instead of read
     #[clap(long, env)]
-    database_url: String,
+    max_lag: Option<u64>,
+}
+
+impl PipelineOpt {
+    fn config(&self) -> PipelineConfig {
+        PipelineConfig {
+            rate_limit_messages: self.rate_limit_messages,
+            rate_limit_characters: self.rate_limit_characters,
+            rate_limit_window: Duration::from_secs(self.rate_limit_window),
+            repeat_window: Duration::from_secs(self.repeat_window),
+            max_repeated_characters: self.max_repeated_characters,
+            max_length: self.max_length.filter(|&max_length| max_length > 0),
+            chunk_length: self.chunk_length,
+            prefetch_depth: self.prefetch_depth,
+            synthesis_concurrency: self.synthesis_concurrency,
+            adaptive_speed: self.adaptive_speed,
+            max_speed_up: self.max_speed_up,
+            max_lag: self.max_lag.map(Duration::from_secs),
+        }
+    }
+}
+
+async fn pipeline(
+    tts_client: tts::Client,
+    pool: &MySqlPool,
+    database: &DatabaseOpt,
+    usage_storage: UsageStorage,
+    config: &PipelineOpt,
+) -> anyhow::Result<Pipeline> {
+    let option_storage = database.option_storage(pool).await?;
+    let guild_settings = database.guild_settings(pool).await?;
+    Ok(Pipeline::new(
+        tts_client,
+        option_storage,
+        usage_storage,
+        guild_settings,
+        config.config(),
+    ))
 }
 
 #[tokio::main]
@@ -197,45 +1172,335 @@ async fn main() -> anyhow::Result<()> {
     dotenv().ok();
     let args = Opt::parse();
 
-    TTS_CLIENT
-        .set(tts::Client::new(
-            args.voicetext_api_key,
-            args.voicevox_api_key,
-        ))
-        .unwrap();
+    let tts_client = tts::Client::new(args.voicetext_api_key, args.voicevox_api_key);
+
+    match args.command {
+        Command::Run(opt) => run(tts_client, opt).await,
+        Command::Simulate(opt) => {
+            let pool = opt.database.connect().await?;
+            // What is simulated is not counted as used, and counts against the
+            // quota as if nothing had been used before.
+            let usage_storage = UsageStorage::in_memory(opt.quota.quota()?);
+            PIPELINE
+                .set(
+                    pipeline(
+                        tts_client,
+                        &pool,
+                        &opt.database,
+                        usage_storage,
+                        &opt.pipeline,
+                    )
+                    .await?,
+                )
+                .ok();
+            let result = ttsbot::simulate(
+                PIPELINE.get().unwrap(),
+                GuildId(opt.guild_id),
+                UserId(opt.user_id),
+                BufReader::new(tokio::io::stdin()),
+                &opt.output_dir,
+            )
+            .await;
+            pool.close().await;
+            result
+        }
+        Command::Synth(opt) => synth(&tts_client, opt).await,
+    }
+}
+
+async fn synth(tts_client: &tts::Client, opt: SynthOpt) -> anyhow::Result<()> {
+    let format = match opt.format {
+        Some(format) => format,
+        None => opt
+            .output
+            .extension()
+            .and_then(|ext| VoiceTextFormat::try_from(ext.to_string_lossy().as_ref()).ok())
+            .unwrap_or(VoiceTextFormat::Wav),
+    };
+
+    let options = match opt.engine {
+        tts::Engine::VoiceText => {
+            let mut options = build_voice_text_options(opt.options.iter())?;
+            options.format = format.clone();
+            tts::Options::VoiceTextOptions(options)
+        }
+        tts::Engine::VoiceVox => {
+            tts::Options::VoiceVoxOptions(build_voice_vox_options(opt.options.iter())?)
+        }
+    };
+
+    let mut audio = tts_client.request(&opt.text, &options).await?;
+    // VOICEVOX only returns WAV.
+    if matches!(options, tts::Options::VoiceVoxOptions(_)) && format != VoiceTextFormat::Wav {
+        audio = ttsbot::audio::transcode(&audio, &format).await?;
+    }
+
+    if opt.output == Path::new("-") {
+        io::stdout().write_all(&audio)?;
+    } else {
+        fs::write(&opt.output, audio)?;
+    }
+
+    Ok(())
+}
 
-    LANGUAGE_DETECTOR
+async fn run(tts_client: tts::Client, args: RunOpt) -> anyhow::Result<()> {
+    let pool = args.database.connect().await?;
+    let usage_storage = UsageStorage::new(pool.clone(), args.quota.quota()?);
+    PIPELINE
         .set(
-            LanguageDetectorBuilder::from_languages(&[Language::English, Language::Japanese])
-                .build(),
+            pipeline(
+                tts_client,
+                &pool,
+                &args.database,
+                usage_storage,
+                &args.pipeline,
+            )
+            .await?,
         )
         .ok();
 
-    let storage = OptionStorage::connect(&args.database_url).await?;
-    OPTION_STORAGE.set(RwLock::new(storage)).ok();
+    let cache_sync_interval = Duration::from_secs(args.cache_sync_interval);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(cache_sync_interval);
+        loop {
+            interval.tick().await;
+            if let Err(why) = PIPELINE.get().unwrap().option_storage().sync().await {
+                println!("Failed to sync option cache: {:?}", why);
+            }
+            if let Err(why) = PIPELINE.get().unwrap().guild_settings().sync().await {
+                println!("Failed to sync guild settings cache: {:?}", why);
+            }
+        }
+    });
+
+    BOT_JOINING_CHANNEL
+        .set(RwLock::new(SessionRegistry::new()))
+        .ok();
+    SESSION_STORAGE.set(SessionStorage::new(pool.clone())).ok();
+    QUEUES.set(RwLock::new(HashMap::new())).ok();
+
+    if let Some(addr) = args.http_addr {
+        let api = Api::new(
+            args.http_token.clone().unwrap(),
+            BOT_JOINING_CHANNEL.get().unwrap(),
+            QUEUES.get().unwrap(),
+        );
+        tokio::spawn(async move {
+            if let Err(why) = api.serve(addr).await {
+                println!("HTTP API ended: {:?}", why);
+            }
+        });
+    }
+
+    let mut bots = Vec::new();
+    let mut clients = Vec::new();
+    for (i, token) in args.discord_tokens.iter().enumerate() {
+        let bot_id = Http::new_with_token(token).get_current_user().await?.id;
+        let songbird = Songbird::serenity();
+
+        let mut builder = Client::builder(token)
+            .event_handler(Handler {
+                bot_id,
+                on_edit: args.on_edit,
+            })
+            .register_songbird_with(songbird.clone());
+        if args.on_edit != EditAction::Ignore {
+            // Edits are read from the cached messages.
+            builder = builder.cache_settings(|s| s.max_messages(MESSAGE_CACHE_SIZE));
+        }
+        if i == 0 {
+            let framework = StandardFramework::new()
+                .configure(|c| c.prefix("."))
+                .on_dispatch_error(dispatch_error)
+                .group(&GENERAL_GROUP);
+            builder = builder.framework(framework);
+        }
+        let client = builder.await.expect("Err creating client");
+
+        bots.push(Bot {
+            id: bot_id,
+            songbird,
+            cache_and_http: client.cache_and_http.clone(),
+        });
+        clients.push(client);
+    }
+    BOTS.set(bots).ok();
+
+    let mut shard_managers = Vec::new();
+    let mut client_tasks = Vec::new();
+    for mut client in clients {
+        shard_managers.push(client.shard_manager.clone());
+        client_tasks.push(tokio::spawn(async move {
+            let _ = client
+                .start()
+                .await
+                .map_err(|why| println!("Client ended: {:?}", why));
+        }));
+    }
+
+    wait_for_signal().await?;
+    println!("Received shutdown signal, shutting down.");
+
+    shutdown(
+        shard_managers,
+        pool,
+        Duration::from_secs(args.shutdown_timeout),
+    )
+    .await;
+    for task in client_tasks {
+        let _ = task.await;
+    }
 
-    BOT_JOINING_CHANNEL.set(RwLock::new(HashMap::new())).ok();
+    Ok(())
+}
 
-    let framework = StandardFramework::new()
-        .configure(|c| c.prefix("."))
-        .group(&GENERAL_GROUP);
+async fn wait_for_signal() -> io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
 
-    let mut client = Client::builder(&args.discord_token)
-        .event_handler(Handler)
-        .framework(framework)
-        .register_songbird()
-        .await
-        .expect("Err creating client");
+/// Stops reading new messages, gives the queues `timeout` to finish, and then
+/// leaves every voice channel and disconnects from Discord and the database.
+async fn shutdown(
+    shard_managers: Vec<Arc<tokio::sync::Mutex<ShardManager>>>,
+    pool: MySqlPool,
+    timeout: Duration,
+) {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+
+    let queues: Vec<_> = QUEUES.get().unwrap().read().values().cloned().collect();
+    let drain = async {
+        for queue in &queues {
+            queue.wait_idle().await;
+        }
+    };
+    if tokio::time::timeout(timeout, drain).await.is_err() {
+        println!("Timed out reading queued messages, cancelling the rest.");
+    }
+    for queue in &queues {
+        queue.close();
+    }
 
-    tokio::spawn(async move {
-        let _ = client
-            .start()
-            .await
-            .map_err(|why| println!("Client ended: {:?}", why));
-    });
+    let sessions: Vec<_> = BOT_JOINING_CHANNEL.get().unwrap().read().iter().collect();
+    for (guild_id, session) in sessions {
+        if let Some(bot) = Bot::get(session.bot_id) {
+            if let Err(why) = bot.songbird.remove(guild_id).await {
+                println!("Failed to leave {}: {:?}", session.voice_channel_id, why);
+            }
+        }
+    }
+
+    for shard_manager in shard_managers {
+        shard_manager.lock().await.shutdown_all().await;
+    }
+
+    // Waits for the queries in flight.
+    pool.close().await;
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Admin)]
+async fn config(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_settings = PIPELINE.get().unwrap().guild_settings();
+    let defaults = PIPELINE.get().unwrap().usage_storage().quota();
+    let guild_id = msg.guild_id.unwrap();
+
+    let content = match (args.single::<String>(), args.single::<String>()) {
+        (Ok(key), Ok(value)) => {
+            match guild_settings
+                .update(guild_id, |settings| {
+                    settings.set(&key, &value)?;
+                    // Admins can tighten the quota of their guild but not
+                    // loosen it, which is up to whoever runs the bot.
+                    let (limit, default) = match key.as_str() {
+                        "daily_quota" => (settings.daily_quota, defaults.guild_daily),
+                        "monthly_quota" => (settings.monthly_quota, defaults.guild_monthly),
+                        _ => return Ok(()),
+                    };
+                    match (limit, default) {
+                        (Some(limit), Some(default)) if limit > default => {
+                            anyhow::bail!("Can't be more than the default of {}", default)
+                        }
+                        _ => Ok(()),
+                    }
+                })
+                .await
+            {
+                Ok(_) => format!("Set {}: {}", key, value),
+                Err(e) => e.to_string(),
+            }
+        }
+        _ => {
+            let settings = guild_settings.get(guild_id).await?;
+            let mut content = MessageBuilder::new();
+            content.push_line("`.config <key> <value>`");
+            for (key, value) in settings.values() {
+                content.push_line(format!("{}: {}", key, value));
+            }
+            content.build()
+        }
+    };
+    check_msg(msg.channel_id.say(&ctx.http, content).await);
 
-    tokio::signal::ctrl_c().await?;
-    println!("Received Ctrl-C, shutting down.");
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+async fn dj(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_settings = PIPELINE.get().unwrap().guild_settings();
+    let guild_id = msg.guild_id.unwrap();
+
+    let subcommand = args.single::<String>().unwrap_or_default();
+    let content = match (subcommand.as_str(), args.single::<RoleId>()) {
+        ("add", Ok(role_id)) => {
+            guild_settings
+                .update(guild_id, |settings| {
+                    if !settings.dj_roles.contains(&role_id.0) {
+                        settings.dj_roles.push(role_id.0);
+                    }
+                    Ok(())
+                })
+                .await?;
+            MessageBuilder::new()
+                .push("Added DJ role: ")
+                .mention(&role_id)
+                .build()
+        }
+        ("remove", Ok(role_id)) => {
+            guild_settings
+                .update(guild_id, |settings| {
+                    settings.dj_roles.retain(|&id| id != role_id.0);
+                    Ok(())
+                })
+                .await?;
+            MessageBuilder::new()
+                .push("Removed DJ role: ")
+                .mention(&role_id)
+                .build()
+        }
+        ("list", _) => {
+            let settings = guild_settings.get(guild_id).await?;
+            if settings.dj_roles.is_empty() {
+                "No DJ roles".to_string()
+            } else {
+                let mut content = MessageBuilder::new();
+                content.push_line("DJ roles:");
+                for &role_id in &settings.dj_roles {
+                    content.mention(&RoleId(role_id)).push_line("");
+                }
+                content.build()
+            }
+        }
+        _ => "`.dj add <role>`, `.dj remove <role>` or `.dj list`".to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, content).await);
 
     Ok(())
 }
@@ -293,6 +1558,128 @@ async fn engine(context: &Context, msg: &Message, mut args: Args) -> CommandResu
     Ok(())
 }
 
+#[command]
+#[only_in(guilds)]
+#[checks(Admin)]
+async fn ignore(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_settings = PIPELINE.get().unwrap().guild_settings();
+    let guild_id = msg.guild_id.unwrap();
+
+    let content = match IgnoreTarget::parse(&mut args) {
+        Some(target) => {
+            guild_settings
+                .update(guild_id, |settings| {
+                    target.add(settings);
+                    Ok(())
+                })
+                .await?;
+            let mut content = MessageBuilder::new();
+            content.push("Ignoring ");
+            target.push(&mut content);
+            content.build()
+        }
+        None if args.is_empty() => {
+            let settings = guild_settings.get(guild_id).await?;
+            let targets = IgnoreTarget::all(&settings);
+            if targets.is_empty() {
+                "Nothing is ignored".to_string()
+            } else {
+                let mut content = MessageBuilder::new();
+                content.push_line("Ignoring:");
+                for target in targets {
+                    target.push(&mut content);
+                    content.push_line("");
+                }
+                content.build()
+            }
+        }
+        None => IGNORE_USAGE.to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, content).await);
+
+    Ok(())
+}
+
+const IGNORE_USAGE: &str =
+    "`.ignore prefix <prefix>`, `.ignore user <user>` or `.ignore role <role>`, and the same with `.unignore`";
+
+/// What `.ignore` and `.unignore` take.
+enum IgnoreTarget {
+    Prefix(String),
+    User(UserId),
+    Role(RoleId),
+}
+
+impl IgnoreTarget {
+    fn parse(args: &mut Args) -> Option<Self> {
+        let kind = args.single::<String>().ok()?;
+        let target = match kind.as_str() {
+            "prefix" => {
+                let prefix = args.rest().trim();
+                if prefix.is_empty() {
+                    return None;
+                }
+                IgnoreTarget::Prefix(prefix.to_string())
+            }
+            "user" => IgnoreTarget::User(args.single().ok()?),
+            "role" => IgnoreTarget::Role(args.single().ok()?),
+            _ => return None,
+        };
+        Some(target)
+    }
+
+    fn all(settings: &GuildSettings) -> Vec<Self> {
+        let prefixes = settings
+            .ignore_prefixes
+            .iter()
+            .cloned()
+            .map(IgnoreTarget::Prefix);
+        let users = settings
+            .ignored_users
+            .iter()
+            .map(|&id| IgnoreTarget::User(UserId(id)));
+        let roles = settings
+            .ignored_roles
+            .iter()
+            .map(|&id| IgnoreTarget::Role(RoleId(id)));
+        prefixes.chain(users).chain(roles).collect()
+    }
+
+    fn add(&self, settings: &mut GuildSettings) {
+        let (list, value) = match self {
+            IgnoreTarget::Prefix(prefix) => {
+                if !settings.ignore_prefixes.contains(prefix) {
+                    settings.ignore_prefixes.push(prefix.clone());
+                }
+                return;
+            }
+            IgnoreTarget::User(user_id) => (&mut settings.ignored_users, user_id.0),
+            IgnoreTarget::Role(role_id) => (&mut settings.ignored_roles, role_id.0),
+        };
+        if !list.contains(&value) {
+            list.push(value);
+        }
+    }
+
+    fn remove(&self, settings: &mut GuildSettings) {
+        match self {
+            IgnoreTarget::Prefix(prefix) => settings.ignore_prefixes.retain(|p| p != prefix),
+            IgnoreTarget::User(user_id) => settings.ignored_users.retain(|&id| id != user_id.0),
+            IgnoreTarget::Role(role_id) => settings.ignored_roles.retain(|&id| id != role_id.0),
+        }
+    }
+
+    fn push(&self, content: &mut MessageBuilder) {
+        match self {
+            IgnoreTarget::Prefix(prefix) => content
+                .push("messages starting with ")
+                .push_mono_safe(prefix),
+            IgnoreTarget::User(user_id) => content.mention(user_id),
+            IgnoreTarget::Role(role_id) => content.mention(role_id),
+        };
+    }
+}
+
 #[command]
 #[only_in(guilds)]
 async fn join(ctx: &Context, msg: &Message) -> CommandResult {
@@ -313,39 +1700,85 @@ async fn join(ctx: &Context, msg: &Message) -> CommandResult {
         }
     };
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
+    // Reuse the bot already in the channel, otherwise pick a bot account that
+    // is in this guild but not in any of its voice channels.
+    let bound = BOT_JOINING_CHANNEL
+        .get()
+        .unwrap()
+        .read()
+        .find_by_channel(guild_id, connect_to)
+        .and_then(|session| Bot::get(session.bot_id));
+    let bot = match bound {
+        Some(bot) => Some(bot),
+        None => {
+            let idle_bot_ids = BOT_JOINING_CHANNEL
+                .get()
+                .unwrap()
+                .read()
+                .idle_bots(guild_id, BOTS.get().unwrap().iter().map(|bot| bot.id));
+            let mut found = None;
+            for bot_id in idle_bot_ids {
+                let bot = Bot::get(bot_id).unwrap();
+                if bot.cache_and_http.cache.guild(guild_id).await.is_some() {
+                    found = Some(bot);
+                    break;
+                }
+            }
+            found
+        }
+    };
+    let bot = match bot {
+        Some(bot) => bot,
+        None => {
+            check_msg(
+                msg.reply(ctx, "No bot account is available for this voice channel")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
 
-    let _handler = manager.join(guild_id, connect_to).await;
+    let (_handler, result) = bot.songbird.join(guild_id, connect_to).await;
+    if let Err(e) = result {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, format!("Failed: {:?}", e))
+                .await,
+        );
 
-    let mut voice_channels = BOT_JOINING_CHANNEL.get().unwrap().write();
-    voice_channels.insert(guild_id, connect_to);
+        return Ok(());
+    }
+
+    bind_session(
+        guild_id,
+        Session {
+            bot_id: bot.id,
+            voice_channel_id: connect_to,
+            text_channel_id: msg.channel_id,
+        },
+    )
+    .await;
 
     Ok(())
 }
 
 #[command]
 #[only_in(guilds)]
+#[checks(VoiceControl)]
 async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
     let guild = msg.guild(&ctx.cache).await.unwrap();
     let guild_id = guild.id;
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-    let has_handler = manager.get(guild_id).is_some();
-
-    if has_handler {
-        if let Err(e) = manager.remove(guild_id).await {
+    if let Some((session, bot)) = find_session(&guild, &msg.author.id) {
+        if let Err(e) = bot.songbird.remove(guild_id).await {
             check_msg(
                 msg.channel_id
                     .say(&ctx.http, format!("Failed: {:?}", e))
                     .await,
             );
         }
+        unbind_session(guild_id, session.bot_id).await;
 
         check_msg(msg.channel_id.say(&ctx.http, "Left voice channel").await);
     } else {
@@ -357,16 +1790,14 @@ async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
 
 #[command]
 #[only_in(guilds)]
+#[checks(VoiceControl)]
 async fn mute(ctx: &Context, msg: &Message) -> CommandResult {
     let guild = msg.guild(&ctx.cache).await.unwrap();
     let guild_id = guild.id;
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    let handler_lock = match manager.get(guild_id) {
+    let handler_lock = match find_session(&guild, &msg.author.id)
+        .and_then(|(_, bot)| bot.songbird.get(guild_id))
+    {
         Some(handler) => handler,
         None => {
             check_msg(msg.reply(ctx, "Not in a voice channel").await);
@@ -394,6 +1825,116 @@ async fn mute(ctx: &Context, msg: &Message) -> CommandResult {
     Ok(())
 }
 
+#[command]
+#[only_in(guilds)]
+#[checks(Admin)]
+async fn ng(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_settings = PIPELINE.get().unwrap().guild_settings();
+    let guild_id = msg.guild_id.unwrap();
+
+    let subcommand = args.single::<String>().unwrap_or_default();
+    let word = args.rest().trim().to_string();
+    let content = match (subcommand.as_str(), word.is_empty()) {
+        ("add", false) => match regex::Regex::new(&ng_word_pattern(&word)) {
+            Ok(pattern) if pattern.is_match("") => {
+                "A pattern matching empty text would beep out nothing".to_string()
+            }
+            Ok(_) => {
+                guild_settings
+                    .update(guild_id, |settings| {
+                        if !settings.ng_words.contains(&word) {
+                            settings.ng_words.push(word.clone());
+                        }
+                        Ok(())
+                    })
+                    .await?;
+                MessageBuilder::new()
+                    .push("Added NG word: ")
+                    .push_mono_safe(&word)
+                    .build()
+            }
+            Err(e) => format!("Invalid regex: {}", e),
+        },
+        ("remove", false) => {
+            let settings = guild_settings
+                .update(guild_id, |settings| {
+                    settings.ng_words.retain(|w| *w != word);
+                    Ok(())
+                })
+                .await?;
+            MessageBuilder::new()
+                .push("Removed NG word: ")
+                .push_mono_safe(&word)
+                .push(format!(" ({} left)", settings.ng_words.len()))
+                .build()
+        }
+        ("list", _) => {
+            let settings = guild_settings.get(guild_id).await?;
+            if settings.ng_words.is_empty() {
+                "No NG words".to_string()
+            } else {
+                let mut content = MessageBuilder::new();
+                content.push_line("NG words:");
+                for word in &settings.ng_words {
+                    content.push_mono_line_safe(word);
+                }
+                content.build()
+            }
+        }
+        _ => "`.ng add <word>`, `.ng add /<regex>/`, `.ng remove <word>` or `.ng list`".to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, content).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn optin(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_settings = PIPELINE.get().unwrap().guild_settings();
+    let user_id = msg.author.id.0;
+
+    guild_settings
+        .update(msg.guild_id.unwrap(), |settings| {
+            settings.opted_out.retain(|&id| id != user_id);
+            Ok(())
+        })
+        .await?;
+    check_msg(
+        msg.channel_id
+            .say(&ctx.http, "Your messages will be read again")
+            .await,
+    );
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn optout(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_settings = PIPELINE.get().unwrap().guild_settings();
+    let user_id = msg.author.id.0;
+
+    guild_settings
+        .update(msg.guild_id.unwrap(), |settings| {
+            if !settings.opted_out.contains(&user_id) {
+                settings.opted_out.push(user_id);
+            }
+            Ok(())
+        })
+        .await?;
+    check_msg(
+        msg.channel_id
+            .say(
+                &ctx.http,
+                "Your messages will not be read. Use `.optin` to undo",
+            )
+            .await,
+    );
+
+    Ok(())
+}
+
 #[command]
 async fn ping(context: &Context, msg: &Message) -> CommandResult {
     check_msg(msg.channel_id.say(&context.http, "Pong!").await);
@@ -423,10 +1964,12 @@ async fn preset(context: &Context, msg: &Message, mut args: Args) -> CommandResu
     match args.single::<String>() {
         Ok(arg) => {
             if let Ok(preset) = tts::Preset::try_from(arg.as_str()) {
-                {
-                    let mut storage = OPTION_STORAGE.get().unwrap().write();
-                    storage.set(&msg.author.id, tts::Options::from(preset)).await.unwrap();
-                }
+                PIPELINE
+                    .get()
+                    .unwrap()
+                    .option_storage()
+                    .set(&msg.author.id, tts::Options::from(preset))
+                    .await?;
 
                 let content = MessageBuilder::new()
                     .push("Set ")
@@ -447,6 +1990,90 @@ async fn preset(context: &Context, msg: &Message, mut args: Args) -> CommandResu
     Ok(())
 }
 
+#[command]
+#[only_in(guilds)]
+#[checks(Admin)]
+async fn rule(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let pipeline = PIPELINE.get().unwrap();
+    let guild_settings = pipeline.guild_settings();
+    let guild_id = msg.guild_id.unwrap();
+
+    let subcommand = args.single::<String>().unwrap_or_default();
+    let content = match subcommand.as_str() {
+        "add" => match args.single_quoted::<String>() {
+            Ok(pattern) => match regex::Regex::new(&pattern) {
+                Ok(_) => {
+                    let rule = Rule {
+                        pattern,
+                        replacement: args.rest().trim().to_string(),
+                    };
+                    let settings = guild_settings
+                        .update(guild_id, |settings| {
+                            settings.rules.push(rule.clone());
+                            Ok(())
+                        })
+                        .await?;
+                    MessageBuilder::new()
+                        .push(format!("Added rule {}: ", settings.rules.len()))
+                        .push_mono_safe(&rule.pattern)
+                        .push(" → ")
+                        .push_mono_safe(&rule.replacement)
+                        .build()
+                }
+                Err(e) => format!("Invalid regex: {}", e),
+            },
+            Err(_) => "`.rule add <regex> <replacement>`".to_string(),
+        },
+        "list" => {
+            let settings = guild_settings.get(guild_id).await?;
+            if settings.rules.is_empty() {
+                "No rules".to_string()
+            } else {
+                let mut content = MessageBuilder::new();
+                for (i, rule) in settings.rules.iter().enumerate() {
+                    content
+                        .push(format!("{}. ", i + 1))
+                        .push_mono_safe(&rule.pattern)
+                        .push(" → ")
+                        .push_mono_line_safe(&rule.replacement);
+                }
+                content.build()
+            }
+        }
+        "remove" => match args.single::<usize>() {
+            Ok(number) => {
+                let mut removed = None;
+                guild_settings
+                    .update(guild_id, |settings| {
+                        if (1..=settings.rules.len()).contains(&number) {
+                            removed = Some(settings.rules.remove(number - 1));
+                        }
+                        Ok(())
+                    })
+                    .await?;
+                match removed {
+                    Some(rule) => MessageBuilder::new()
+                        .push(format!("Removed rule {}: ", number))
+                        .push_mono_safe(&rule.pattern)
+                        .build(),
+                    None => format!("No rule {}", number),
+                }
+            }
+            Err(_) => "`.rule remove <number>`".to_string(),
+        },
+        "test" if !args.rest().trim().is_empty() => {
+            let text = pipeline.rewrite(guild_id, args.rest()).await?;
+            MessageBuilder::new().push_safe(text).build()
+        }
+        _ => "`.rule add <regex> <replacement>`, `.rule list`, `.rule remove <number>` or \
+              `.rule test <text>`. Write `${1}` in a replacement for the first group."
+            .to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, content).await);
+
+    Ok(())
+}
+
 #[command]
 async fn set(context: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let print_usage = move || async {
@@ -472,8 +2099,10 @@ async fn set(context: &Context, msg: &Message, mut args: Args) -> CommandResult
                 tts::Engine::VoiceText => {
                     match build_voice_text_options(args.iter::<String>().map(|a| a.unwrap())) {
                         Ok(options) => {
-                            let mut storage = OPTION_STORAGE.get().unwrap().write();
-                            storage
+                            PIPELINE
+                                .get()
+                                .unwrap()
+                                .option_storage()
                                 .set(&msg.author.id, tts::Options::VoiceTextOptions(options))
                                 .await?;
                         }
@@ -483,8 +2112,10 @@ async fn set(context: &Context, msg: &Message, mut args: Args) -> CommandResult
                 tts::Engine::VoiceVox => {
                     match build_voice_vox_options(args.iter::<String>().map(|a| a.unwrap())) {
                         Ok(options) => {
-                            let mut storage = OPTION_STORAGE.get().unwrap().write();
-                            storage
+                            PIPELINE
+                                .get()
+                                .unwrap()
+                                .option_storage()
                                 .set(&msg.author.id, tts::Options::VoiceVoxOptions(options))
                                 .await?;
                         }
@@ -504,38 +2135,61 @@ async fn set(context: &Context, msg: &Message, mut args: Args) -> CommandResult
 
 #[command]
 #[only_in(guilds)]
+#[checks(VoiceControl)]
 async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
     let guild = msg.guild(&ctx.cache).await.unwrap();
     let guild_id = guild.id;
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-    let handler_lock = match manager.get(guild_id) {
-        Some(handler) => handler,
+    let queue = match find_session(&guild, &msg.author.id)
+        .and_then(|(session, _)| find_queue(guild_id, session.bot_id))
+    {
+        Some(queue) => queue,
         None => {
             check_msg(msg.reply(ctx, "Not in a voice channel").await);
             return Ok(());
         }
     };
-    let mut handler = handler_lock.lock().await;
-    handler.stop();
+    queue.clear();
+    queue.skip();
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Admin)]
+async fn unignore(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_settings = PIPELINE.get().unwrap().guild_settings();
+
+    let content = match IgnoreTarget::parse(&mut args) {
+        Some(target) => {
+            guild_settings
+                .update(msg.guild_id.unwrap(), |settings| {
+                    target.remove(settings);
+                    Ok(())
+                })
+                .await?;
+            let mut content = MessageBuilder::new();
+            content.push("No longer ignoring ");
+            target.push(&mut content);
+            content.build()
+        }
+        None => IGNORE_USAGE.to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, content).await);
+
     Ok(())
 }
 
 #[command]
 #[only_in(guilds)]
+#[checks(VoiceControl)]
 async fn unmute(ctx: &Context, msg: &Message) -> CommandResult {
     let guild = msg.guild(&ctx.cache).await.unwrap();
     let guild_id = guild.id;
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    if let Some(handler_lock) = manager.get(guild_id) {
+    if let Some(handler_lock) =
+        find_session(&guild, &msg.author.id).and_then(|(_, bot)| bot.songbird.get(guild_id))
+    {
         let mut handler = handler_lock.lock().await;
         if let Err(e) = handler.mute(false).await {
             check_msg(
@@ -557,6 +2211,54 @@ async fn unmute(ctx: &Context, msg: &Message) -> CommandResult {
     Ok(())
 }
 
+#[command]
+#[only_in(guilds)]
+async fn usage(ctx: &Context, msg: &Message) -> CommandResult {
+    let pipeline = PIPELINE.get().unwrap();
+    let usage_storage = pipeline.usage_storage();
+    let settings = pipeline.guild_settings().get(msg.guild_id.unwrap()).await?;
+    let quota = usage_storage.quota().for_guild(&settings);
+
+    let mut content = MessageBuilder::new();
+    for scope in [
+        Scope::Guild(msg.guild_id.unwrap()),
+        Scope::User(msg.author.id),
+    ] {
+        let usage = usage_storage.usage(scope).await?;
+        match scope {
+            Scope::Guild(_) => content.push_line("This server:"),
+            Scope::User(user_id) => content.mention(&user_id).push_line(":"),
+        };
+        for (period, name) in [(Period::Day, "Today"), (Period::Month, "This month")] {
+            content.push(format!("{}: {}", name, quota.metered(&usage, period)));
+            if let Some(limit) = quota.limit(scope, period) {
+                content.push(format!(" / {}", limit));
+            }
+            content.push(" characters");
+            let breakdown: Vec<String> = usage
+                .iter()
+                .map(|(engine, totals)| {
+                    let free = tts::Engine::try_from(engine.as_str())
+                        .map_or(false, |e| !quota.is_metered(&e));
+                    format!(
+                        "{} {}{}",
+                        engine,
+                        totals.get(period),
+                        if free { " free" } else { "" }
+                    )
+                })
+                .collect();
+            if !breakdown.is_empty() {
+                content.push(format!(" ({})", breakdown.join(", ")));
+            }
+            content.push_line("");
+        }
+    }
+    check_msg(msg.channel_id.say(&ctx.http, content.build()).await);
+
+    Ok(())
+}
+
 /// Checks that a message successfully sent; if not, then logs why to stdout.
 fn check_msg(result: SerenityResult<Message>) {
     if let Err(why) = result {
@@ -0,0 +1,87 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+
+pub static MESSAGES_READ: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ttsbot_messages_read_total",
+        "Chat messages queued to be read",
+        &["guild_id"]
+    )
+    .unwrap()
+});
+
+pub static CHARACTERS_SYNTHESIZED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ttsbot_synthesized_characters_total",
+        "Characters successfully synthesized",
+        &["engine"]
+    )
+    .unwrap()
+});
+
+pub static SYNTHESIS_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "ttsbot_synthesis_duration_seconds",
+        "Time taken by successful TTS API requests",
+        &["engine"]
+    )
+    .unwrap()
+});
+
+pub static API_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ttsbot_tts_api_errors_total",
+        "Failed TTS API requests",
+        &["engine", "kind"]
+    )
+    .unwrap()
+});
+
+pub static OPTION_CACHE_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ttsbot_option_cache_requests_total",
+        "Lookups of user options, by whether they were served from the cache",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub static QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "ttsbot_queue_depth",
+        "Utterances waiting to be read",
+        &["guild_id", "bot_id"]
+    )
+    .unwrap()
+});
+
+pub static VOICE_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "ttsbot_voice_connections",
+        "Sessions whose voice connection is up"
+    )
+    .unwrap()
+});
+
+/// A short label for why a TTS API request failed, e.g. "timeout" or "503".
+pub fn error_kind(error: &anyhow::Error) -> String {
+    match error.downcast_ref::<reqwest::Error>() {
+        Some(error) if error.is_timeout() => "timeout".to_string(),
+        Some(error) if error.is_connect() => "connect".to_string(),
+        Some(error) => match error.status() {
+            Some(status) => status.as_u16().to_string(),
+            None => "request".to_string(),
+        },
+        None => "other".to_string(),
+    }
+}
+
+/// The metrics in the Prometheus text format.
+pub fn render() -> anyhow::Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
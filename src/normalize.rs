@@ -0,0 +1,224 @@
+use regex::Regex;
+
+/// Stands in for the words beeped out. It is in the Private Use Area, so it is
+/// never typed in a message that has been through `strip_beeps`.
+pub const BEEP: char = '\u{E000}';
+
+pub fn strip_beeps(text: &str) -> String {
+    text.replace(BEEP, "")
+}
+
+/// Replaces what the patterns match with a `BEEP`. Empty matches are ignored
+/// so that a pattern like `a*` doesn't beep between every character.
+pub fn mask(text: &str, patterns: &[Regex]) -> String {
+    let mut masked = text.to_string();
+    for pattern in patterns {
+        let mut replaced = String::with_capacity(masked.len());
+        let mut last = 0;
+        for m in pattern.find_iter(&masked) {
+            if m.as_str().is_empty() {
+                continue;
+            }
+            replaced.push_str(&masked[last..m.start()]);
+            replaced.push(BEEP);
+            last = m.end();
+        }
+        replaced.push_str(&masked[last..]);
+        masked = replaced;
+    }
+    masked
+}
+
+/// Applies the rewrite rules in order, each to the result of the one before.
+pub fn rewrite(text: &str, rules: &[(Regex, String)]) -> String {
+    let mut rewritten = text.to_string();
+    for (pattern, replacement) in rules {
+        rewritten = pattern
+            .replace_all(&rewritten, replacement.as_str())
+            .into_owned();
+    }
+    rewritten
+}
+
+/// Shortens runs of the same character to `max_run`, reading a run of "w" as
+/// laughter. Digits are left alone so that numbers keep their value.
+pub fn shorten_runs(text: &str, max_run: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut shortened = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let mut j = i;
+        while j < chars.len() && chars[j] == c {
+            j += 1;
+        }
+        let run = j - i;
+
+        let prev = i.checked_sub(1).map(|k| chars[k]);
+        let next = chars.get(j).copied();
+        if is_laughter(c, prev, next) {
+            shortened.push_str(if run >= 2 { "わらわら" } else { "わら" });
+        } else if c.is_numeric() {
+            shortened.extend(&chars[i..j]);
+        } else {
+            shortened.extend(std::iter::repeat(c).take(run.min(max_run)));
+        }
+        i = j;
+    }
+    shortened
+}
+
+/// Whether "w" is laughter rather than a part of a word or a URL, as in
+/// "草w" or "wwww" but not "www.example.com" or "wow".
+fn is_laughter(c: char, prev: Option<char>, next: Option<char>) -> bool {
+    matches!(c, 'w' | 'W' | 'ｗ' | 'Ｗ')
+        && prev.map_or(true, |p| !p.is_ascii_alphanumeric())
+        && next.map_or(true, |n| !n.is_ascii_graphic())
+}
+
+const SENTENCE_ENDS: &[char] = &['。', '．', '！', '？', '!', '?', '\n'];
+const CLAUSE_ENDS: &[char] = &['、', '，', ',', ' ', '　'];
+
+/// Cuts the text off at `max_length` characters, saying that the rest is
+/// omitted.
+pub fn truncate(text: &str, max_length: usize) -> String {
+    if text.chars().count() <= max_length {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_length).collect();
+    truncated.push_str("、以下略");
+    truncated
+}
+
+/// Splits the text into chunks of at most `max_length` characters, breaking
+/// at the ends of sentences where possible.
+pub fn split_chunks(text: &str, max_length: usize) -> Vec<String> {
+    let mut chunks: Vec<String> = Vec::new();
+    for piece in pieces(text, max_length) {
+        match chunks.last_mut() {
+            Some(last) if last.chars().count() + piece.chars().count() <= max_length => {
+                last.push_str(piece);
+            }
+            _ => chunks.push(piece.to_string()),
+        }
+    }
+    chunks
+        .into_iter()
+        .map(|chunk| chunk.trim().to_string())
+        .filter(|chunk| !chunk.is_empty())
+        .collect()
+}
+
+/// Splits the text into pieces of at most `max_length` characters at the ends
+/// of sentences, then of clauses, and then anywhere.
+fn pieces(text: &str, max_length: usize) -> Vec<&str> {
+    if text.chars().count() <= max_length {
+        return vec![text];
+    }
+    for ends in [SENTENCE_ENDS, CLAUSE_ENDS] {
+        let parts = split_after(text, ends);
+        if parts.len() > 1 {
+            return parts
+                .into_iter()
+                .flat_map(|part| pieces(part, max_length))
+                .collect();
+        }
+    }
+
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let end = rest
+            .char_indices()
+            .nth(max_length.max(1))
+            .map_or(rest.len(), |(i, _)| i);
+        parts.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    parts
+}
+
+/// Splits the text after each run of `ends`, keeping them.
+fn split_after<'a>(text: &'a str, ends: &[char]) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if ends.contains(&c) && !matches!(chars.peek(), Some((_, next)) if ends.contains(next)) {
+            let end = i + c.len_utf8();
+            parts.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() {
+        parts.push(&text[start..]);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rewrite() {
+        let rules = [
+            (Regex::new(r"(\d+)円").unwrap(), "$1えん".to_string()),
+            (Regex::new("！{2,}").unwrap(), "！".to_string()),
+            (Regex::new(r"#(\d+)").unwrap(), "イシュー$1".to_string()),
+            (Regex::new("イシュー").unwrap(), "issue ".to_string()),
+        ];
+        assert_eq!(rewrite("#12 は100円！！！", &rules), "issue 12 は100えん！");
+        assert_eq!(rewrite("そのまま", &[]), "そのまま");
+    }
+
+    #[test]
+    fn test_shorten_runs() {
+        assert_eq!(shorten_runs("wwwwww", 3), "わらわら");
+        assert_eq!(shorten_runs("草w", 3), "草わら");
+        assert_eq!(shorten_runs("ｗｗｗ 了解", 3), "わらわら 了解");
+        assert_eq!(shorten_runs("wow", 3), "wow");
+        assert_eq!(shorten_runs("www.example.com", 3), "www.example.com");
+        assert_eq!(
+            shorten_runs("すごーーーーーい！！！！", 3),
+            "すごーーーい！！！"
+        );
+        assert_eq!(shorten_runs("1000000円", 3), "1000000円");
+    }
+
+    #[test]
+    fn test_mask() {
+        let patterns = [
+            Regex::new("(?i)baka").unwrap(),
+            Regex::new("ア+ホ").unwrap(),
+        ];
+        assert_eq!(
+            mask("BAKAとアアホと馬鹿", &patterns),
+            format!("{}と{}と馬鹿", BEEP, BEEP)
+        );
+        assert_eq!(mask("ばか", &[Regex::new("x*").unwrap()]), "ばか");
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("あいうえお", 5), "あいうえお");
+        assert_eq!(truncate("あいうえおか", 5), "あいうえお、以下略");
+    }
+
+    #[test]
+    fn test_split_chunks() {
+        assert_eq!(
+            split_chunks("おはよう。今日は晴れ！散歩に行こう？", 12),
+            vec!["おはよう。今日は晴れ！", "散歩に行こう？"]
+        );
+        assert_eq!(
+            split_chunks("とても長い文で、読点で区切るしかない", 10),
+            vec!["とても長い文で、", "読点で区切るしかない"]
+        );
+        assert_eq!(
+            split_chunks("あいうえおかきく", 3),
+            vec!["あいう", "えおか", "きく"]
+        );
+        assert_eq!(split_chunks("短い", 10), vec!["短い"]);
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Context as _;
 
+use crate::tts;
 use crate::tts::voice_text::{VoiceTextOptions, VoiceTextOptionsBuilder};
 use crate::tts::voice_vox::{VoiceVoxOptions, VoiceVoxOptionsBuilder};
 
@@ -73,3 +74,15 @@ where
     let options = builder.build()?;
     Ok(options)
 }
+
+pub fn build_options<A, S>(engine: &tts::Engine, args: A) -> anyhow::Result<tts::Options>
+where
+    A: Iterator<Item = S>,
+    S: AsRef<str>,
+{
+    let options = match engine {
+        tts::Engine::VoiceText => tts::Options::VoiceTextOptions(build_voice_text_options(args)?),
+        tts::Engine::VoiceVox => tts::Options::VoiceVoxOptions(build_voice_vox_options(args)?),
+    };
+    Ok(options)
+}
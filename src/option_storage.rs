@@ -1,63 +1,110 @@
-use std::collections::HashMap;
+use std::time::Duration;
 
-use serde::{Deserialize, Serialize};
+use parking_lot::Mutex;
 use serenity::model::id::UserId;
 use sqlx::mysql::MySqlPool;
 
+use crate::cache::{TtlCache, DEFAULT_CACHE_CAPACITY};
+use crate::invalidation::Invalidations;
+use crate::metrics;
 use crate::tts;
-use crate::tts::voice_text::{VoiceTextFormat, VoiceTextOptions, VoiceTextSpeaker};
-
-const DEFAULT_OPTIONS: tts::Options = tts::Options::VoiceTextOptions(VoiceTextOptions {
-    speaker: VoiceTextSpeaker::Show,
-    format: VoiceTextFormat::Wav,
-    emotion: None,
-    emotion_level: 2,
-    pitch: 100,
-    speed: 100,
-    volume: 100,
-});
 
 pub struct OptionStorage {
-    cache: HashMap<u64, tts::Options>,
-    pool: MySqlPool,
+    // `None` is cached for users who have never run `.set` so that they don't
+    // hit the database on every message.
+    cache: Mutex<TtlCache<u64, Option<tts::Options>>>,
+    /// `None` keeps the options in memory only, as in tests.
+    database: Option<(MySqlPool, Invalidations)>,
 }
 
 impl OptionStorage {
-    pub async fn connect(database_url: &str) -> anyhow::Result<Self> {
-        let pool = MySqlPool::connect(database_url).await?;
-        let records = sqlx::query!("SELECT user_id, options FROM options")
-            .fetch_all(&pool)
-            .await?;
+    pub async fn new(
+        pool: MySqlPool,
+        cache_capacity: usize,
+        cache_ttl: Duration,
+    ) -> anyhow::Result<Self> {
+        let invalidations = Invalidations::follow(&pool, "options").await?;
         Ok(Self {
-            cache: HashMap::from_iter(records.into_iter().map(|r| {
-                (
-                    r.user_id,
-                    serde_json::from_value(r.options.unwrap()).unwrap(),
-                )
-            })),
-            pool,
+            cache: Mutex::new(TtlCache::new(cache_capacity, cache_ttl)),
+            database: Some((pool, invalidations)),
         })
     }
 
-    pub fn get(&self, user_id: &UserId) -> tts::Options {
-        self.cache
-            .get(&user_id.0)
-            .cloned()
-            .unwrap_or(DEFAULT_OPTIONS)
+    /// Options kept in memory only, which never expire.
+    pub fn in_memory() -> Self {
+        Self {
+            cache: Mutex::new(TtlCache::new(DEFAULT_CACHE_CAPACITY, Duration::MAX)),
+            database: None,
+        }
     }
 
-    pub async fn set(&mut self, user_id: &UserId, options: tts::Options) -> anyhow::Result<()> {
-        sqlx::query!(
-            r#"
+    pub async fn get(&self, user_id: &UserId) -> anyhow::Result<tts::Options> {
+        if let Some(options) = self.cache.lock().get(&user_id.0) {
+            metrics::OPTION_CACHE_REQUESTS
+                .with_label_values(&["hit"])
+                .inc();
+            return Ok(options.unwrap_or_default());
+        }
+        metrics::OPTION_CACHE_REQUESTS
+            .with_label_values(&["miss"])
+            .inc();
+        let (pool, _) = match &self.database {
+            Some(database) => database,
+            None => return Ok(tts::Options::default()),
+        };
+
+        let record = sqlx::query!("SELECT options FROM options WHERE user_id = ?", user_id.0)
+            .fetch_optional(pool)
+            .await?;
+        let options: Option<tts::Options> = match record.and_then(|r| r.options) {
+            Some(options) => Some(serde_json::from_value(options)?),
+            None => None,
+        };
+        self.cache.lock().insert(user_id.0, options.clone());
+
+        Ok(options.unwrap_or_default())
+    }
+
+    pub async fn set(&self, user_id: &UserId, options: tts::Options) -> anyhow::Result<()> {
+        if let Some((pool, invalidations)) = &self.database {
+            sqlx::query!(
+                r#"
 REPLACE INTO options (user_id, options)
 VALUES (?, ?)
-            "#,
-            user_id.0,
-            serde_json::to_string(&options)?
-        )
-        .execute(&self.pool)
-        .await?;
-        self.cache.insert(user_id.0, options);
+                "#,
+                user_id.0,
+                serde_json::to_string(&options)?
+            )
+            .execute(pool)
+            .await?;
+            invalidations.publish(pool, user_id.0).await?;
+        }
+        self.cache.lock().insert(user_id.0, Some(options));
         Ok(())
     }
+
+    /// Invalidates the options changed by other processes sharing the
+    /// database. Meant to be called periodically.
+    pub async fn sync(&self) -> anyhow::Result<()> {
+        let (pool, invalidations) = match &self.database {
+            Some(database) => database,
+            None => return Ok(()),
+        };
+        let user_ids = invalidations.poll(pool).await?;
+        let mut cache = self.cache.lock();
+        for user_id in user_ids {
+            cache.remove(&user_id);
+        }
+        Ok(())
+    }
+
+    /// Drops the cached options of the user so that the next `get` reads them
+    /// from the database again.
+    pub fn invalidate(&self, user_id: &UserId) {
+        self.cache.lock().remove(&user_id.0);
+    }
+
+    pub fn invalidate_all(&self) {
+        self.cache.lock().clear();
+    }
 }
@@ -0,0 +1,764 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use lingua::{Language, LanguageDetector, LanguageDetectorBuilder};
+use serenity::async_trait;
+use serenity::model::id::{ChannelId, GuildId, MessageId, RoleId, UserId};
+use strum::IntoEnumIterator;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+use crate::audio::{self, Clip};
+use crate::directive::apply_directive;
+use crate::emoji;
+use crate::guild_settings::{GuildSettings, GuildSettingsStorage};
+use crate::kana;
+use crate::metrics;
+use crate::normalize;
+use crate::queue::{SpeechQueue, Utterance};
+use crate::spam::{RateLimiter, RepeatFilter};
+use crate::tts;
+use crate::usage::UsageStorage;
+use crate::OptionStorage;
+
+/// A chat message as seen by the pipeline.
+#[derive(Clone, Debug)]
+pub struct ChatMessage {
+    pub id: MessageId,
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub author_id: UserId,
+    pub author_is_bot: bool,
+    pub author_roles: Vec<RoleId>,
+    /// Whether the message was posted through a webhook, such as a chat
+    /// bridge.
+    pub is_webhook: bool,
+    /// The content as it was typed.
+    pub content: String,
+    /// The content with mentions resolved, which is what is read.
+    pub text: String,
+    /// What the message replies to, read before the text, as in
+    /// "○○さんへの返信".
+    pub reply: Option<String>,
+    /// Read after the text, such as the attachments and stickers.
+    pub extras: Vec<String>,
+    /// The text of each embed, read after the content for bots, such as chat
+    /// bridges, that post nothing else.
+    pub embeds: Vec<String>,
+    /// Whether this is an edit of a message seen before, which is neither
+    /// counted against the rate limit again nor taken for a repeat of itself.
+    pub edited: bool,
+}
+
+/// What becomes of a chat message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// The message is read as these utterances.
+    Read(Vec<Utterance>),
+    /// The message is not to be read, e.g. its author is not in the voice
+    /// channel.
+    Ignored,
+    /// The message is dropped as spam by the rate limiter or the repeat
+    /// filter.
+    Throttled,
+}
+
+/// The chat side of the bot, e.g. a Discord guild.
+#[async_trait]
+pub trait Chat: Send + Sync {
+    /// The bot account the pipeline runs as.
+    fn bot_id(&self) -> UserId;
+
+    /// The voice channel the user is in, if any.
+    async fn voice_channel_of(&self, guild_id: GuildId, user_id: UserId) -> Option<ChannelId>;
+
+    /// The voice channel the bot account reads in the guild, if any.
+    fn reading_channel(&self, guild_id: GuildId) -> Option<ChannelId>;
+
+    /// The text channel the bot account was summoned from in the guild, if
+    /// any.
+    fn text_channel(&self, guild_id: GuildId) -> Option<ChannelId>;
+}
+
+/// The voice side of the bot, e.g. a Discord voice connection.
+#[async_trait]
+pub trait Voice: Send + Sync {
+    /// Audio that is ready to be played at once.
+    type Prepared: Send + 'static;
+
+    /// Decodes the audio ahead of being played.
+    async fn prepare(&self, audio: Vec<u8>) -> anyhow::Result<Self::Prepared>;
+
+    /// Plays the audio, returning once it has been played through or stopped.
+    async fn play(&self, prepared: Self::Prepared) -> anyhow::Result<()>;
+
+    /// Stops the audio being played, if any.
+    fn stop(&self);
+}
+
+/// Tunables of the pipeline.
+#[derive(Clone, Debug)]
+pub struct PipelineConfig {
+    /// Messages a user can have read per `rate_limit_window`.
+    pub rate_limit_messages: Option<usize>,
+    /// Characters a user can have read per `rate_limit_window`.
+    pub rate_limit_characters: Option<usize>,
+    pub rate_limit_window: Duration,
+    /// How long a message identical to the previous one is dropped for.
+    pub repeat_window: Duration,
+    /// Runs of the same character longer than this are shortened.
+    pub max_repeated_characters: usize,
+    /// Messages longer than this are cut off with "以下略".
+    pub max_length: Option<usize>,
+    /// Messages longer than this are read in chunks split at the ends of
+    /// sentences, which can be skipped one by one.
+    pub chunk_length: usize,
+    /// Utterances of a session synthesized ahead of being read.
+    pub prefetch_depth: usize,
+    /// Requests each engine is sent at once, across sessions.
+    pub synthesis_concurrency: usize,
+    /// Whether to speak faster as utterances pile up in the queue.
+    pub adaptive_speed: bool,
+    /// How many times as fast adaptive speed can make speech.
+    pub max_speed_up: f64,
+    /// Utterances queued longer ago than this are dropped.
+    pub max_lag: Option<Duration>,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            rate_limit_messages: None,
+            rate_limit_characters: None,
+            rate_limit_window: Duration::from_secs(10),
+            repeat_window: Duration::from_secs(30),
+            max_repeated_characters: 3,
+            max_length: Some(300),
+            chunk_length: 100,
+            prefetch_depth: 2,
+            synthesis_concurrency: 4,
+            adaptive_speed: false,
+            max_speed_up: 1.5,
+            max_lag: None,
+        }
+    }
+}
+
+/// How much faster each utterance waiting in the queue makes speech with
+/// adaptive speed.
+const SPEED_UP_PER_UTTERANCE: f64 = 0.1;
+
+/// Audio being synthesized and prepared ahead of being played.
+type Prefetch<P> = JoinHandle<anyhow::Result<P>>;
+
+/// Turns chat messages into utterances and reads them aloud.
+pub struct Pipeline {
+    tts_client: tts::Client,
+    option_storage: OptionStorage,
+    usage_storage: UsageStorage,
+    guild_settings: GuildSettingsStorage,
+    config: PipelineConfig,
+    rate_limiter: RateLimiter,
+    repeat_filter: RepeatFilter,
+    synthesis_permits: HashMap<tts::Engine, Semaphore>,
+    language_detector: LanguageDetector,
+}
+
+impl Pipeline {
+    pub fn new(
+        tts_client: tts::Client,
+        option_storage: OptionStorage,
+        usage_storage: UsageStorage,
+        guild_settings: GuildSettingsStorage,
+        config: PipelineConfig,
+    ) -> Self {
+        Self {
+            tts_client,
+            option_storage,
+            usage_storage,
+            guild_settings,
+            rate_limiter: RateLimiter::new(
+                config.rate_limit_window,
+                config.rate_limit_messages,
+                config.rate_limit_characters,
+            ),
+            repeat_filter: RepeatFilter::new(config.repeat_window),
+            synthesis_permits: tts::Engine::iter()
+                .map(|engine| (engine, Semaphore::new(config.synthesis_concurrency)))
+                .collect(),
+            config,
+            language_detector: LanguageDetectorBuilder::from_languages(&[
+                Language::English,
+                Language::Japanese,
+            ])
+            .build(),
+        }
+    }
+
+    pub fn tts_client(&self) -> &tts::Client {
+        &self.tts_client
+    }
+
+    pub fn option_storage(&self) -> &OptionStorage {
+        &self.option_storage
+    }
+
+    pub fn usage_storage(&self) -> &UsageStorage {
+        &self.usage_storage
+    }
+
+    pub fn guild_settings(&self) -> &GuildSettingsStorage {
+        &self.guild_settings
+    }
+
+    /// Decides whether the message is read, and how, splitting long ones into
+    /// several utterances. Returns `QuotaExceeded` as the error for messages
+    /// refused because of the quota.
+    pub async fn process(&self, chat: &impl Chat, msg: &ChatMessage) -> anyhow::Result<Outcome> {
+        if msg.author_id == chat.bot_id() {
+            return Ok(Outcome::Ignored);
+        }
+        let settings = self.guild_settings.get(msg.guild_id).await?;
+        if settings.ignores(msg) {
+            return Ok(Outcome::Ignored);
+        }
+
+        if msg.author_is_bot || msg.is_webhook {
+            // Bots are not in voice channels, so theirs are read in the text
+            // channel the bot account was summoned from.
+            if chat.reading_channel(msg.guild_id).is_none()
+                || chat.text_channel(msg.guild_id) != Some(msg.channel_id)
+            {
+                return Ok(Outcome::Ignored);
+            }
+        } else {
+            let authors_voice_channel_id = chat.voice_channel_of(msg.guild_id, msg.author_id).await;
+            if authors_voice_channel_id.is_none()
+                || authors_voice_channel_id != chat.reading_channel(msg.guild_id)
+            {
+                return Ok(Outcome::Ignored);
+            }
+        }
+
+        let saved = self.option_storage.get(&msg.author_id).await?;
+        let (mut options, text) = compose(msg, &settings, saved);
+
+        let rules = self.guild_settings.rules(&settings);
+        let text = normalize::rewrite(&text, &rules);
+        let mut text = normalize::strip_beeps(&text);
+        if settings.kaomoji {
+            text = emoji::replace_kaomoji(&text);
+        }
+        let text = emoji::replace_emoji(&text, settings.emoji);
+        let text = normalize::shorten_runs(&text, self.config.max_repeated_characters);
+        // Told before masking, which would leave nothing to tell by in a
+        // message of NG words only.
+        if !self.is_japanese(&text) {
+            return Ok(Outcome::Ignored);
+        }
+        let ng_words = self.guild_settings.ng_words(&settings);
+        let mut text = normalize::mask(&text, &ng_words);
+        if settings.english_to_kana {
+            text = kana::english_to_kana(&text);
+        }
+        if let Some(max_length) = self.config.max_length {
+            text = normalize::truncate(&text, max_length);
+        }
+
+        let bot_id = chat.bot_id();
+        if !msg.edited {
+            if self.repeat_filter.is_repeat(msg.guild_id, bot_id, &text)
+                || !self
+                    .rate_limiter
+                    .try_acquire(msg.author_id, text.chars().count())
+            {
+                return Ok(Outcome::Throttled);
+            }
+            self.repeat_filter.remember(msg.guild_id, bot_id, &text);
+        }
+
+        let quota = self.usage_storage.quota().for_guild(&settings);
+        if quota.is_metered(&options.engine()) {
+            if let Some(exceeded) = self
+                .usage_storage
+                .check(&quota, msg.guild_id, msg.author_id)
+                .await?
+            {
+                match &quota.fallback {
+                    Some(fallback) => options = fallback.clone(),
+                    None => return Err(exceeded.into()),
+                }
+            }
+        }
+
+        metrics::MESSAGES_READ
+            .with_label_values(&[&msg.guild_id.to_string()])
+            .inc();
+        Ok(Outcome::Read(
+            normalize::split_chunks(&text, self.config.chunk_length)
+                .into_iter()
+                .map(|chunk| Utterance {
+                    text: chunk,
+                    options: options.clone(),
+                    guild_id: msg.guild_id,
+                    author_id: Some(msg.author_id),
+                    message_id: Some(msg.id),
+                })
+                .collect(),
+        ))
+    }
+
+    /// Applies the rewrite rules of the guild to the text.
+    pub async fn rewrite(&self, guild_id: GuildId, text: &str) -> anyhow::Result<String> {
+        let settings = self.guild_settings.get(guild_id).await?;
+        let rules = self.guild_settings.rules(&settings);
+        Ok(normalize::rewrite(text, &rules))
+    }
+
+    pub fn is_japanese(&self, text: &str) -> bool {
+        matches!(
+            self.language_detector.detect_language_of(text),
+            Some(Language::Japanese)
+        )
+    }
+
+    /// Synthesizes the utterance, splicing a beep into the audio wherever a
+    /// word has been masked.
+    pub async fn synthesize(&self, utterance: &Utterance) -> anyhow::Result<Vec<u8>> {
+        if !utterance.text.contains(normalize::BEEP) {
+            return self.request(utterance, &utterance.text).await;
+        }
+
+        let mut clips = Vec::new();
+        for (i, piece) in utterance.text.split(normalize::BEEP).enumerate() {
+            if i > 0 {
+                clips.push(Clip::Beep);
+            }
+            // Engines refuse text with nothing to read, such as a lone "、".
+            if piece.chars().any(char::is_alphanumeric) {
+                clips.push(Clip::Audio(self.request(utterance, piece).await?));
+            }
+        }
+        audio::concat(&clips).await
+    }
+
+    async fn request(&self, utterance: &Utterance, text: &str) -> anyhow::Result<Vec<u8>> {
+        let engine = utterance.options.engine();
+        let _permit = self.synthesis_permits[&engine].acquire().await?;
+        let timer = metrics::SYNTHESIS_DURATION
+            .with_label_values(&[&engine.to_string()])
+            .start_timer();
+        let result = self.tts_client.request(text, &utterance.options).await;
+        match &result {
+            Ok(_) => {
+                timer.observe_duration();
+                let characters = text.chars().count() as u64;
+                metrics::CHARACTERS_SYNTHESIZED
+                    .with_label_values(&[&engine.to_string()])
+                    .inc_by(characters);
+                if let Err(why) = self
+                    .usage_storage
+                    .record(utterance.guild_id, utterance.author_id, &engine, characters)
+                    .await
+                {
+                    println!("Failed to record usage: {:?}", why);
+                }
+            }
+            Err(why) => {
+                timer.stop_and_discard();
+                metrics::API_ERRORS
+                    .with_label_values(&[&engine.to_string(), &metrics::error_kind(why)])
+                    .inc();
+            }
+        }
+        result
+    }
+
+    /// Reads the utterances in the queue aloud one by one until it is closed.
+    /// The utterances coming up are synthesized and prepared while one is
+    /// played so that they are read without a gap.
+    pub async fn speak<V>(&'static self, queue: Arc<SpeechQueue>, voice: V)
+    where
+        V: Voice + 'static,
+    {
+        let voice = Arc::new(voice);
+        let mut prefetched = VecDeque::new();
+        loop {
+            if let Some(max_lag) = self.config.max_lag {
+                let dropped = queue.drop_stale(max_lag);
+                if dropped > 0 {
+                    println!("Dropped {} utterances queued too long ago", dropped);
+                }
+            }
+            let utterance = match queue.next().await {
+                Some(utterance) => utterance,
+                None => break,
+            };
+
+            let task = match prefetched.iter().position(|(next, _)| *next == utterance) {
+                Some(i) => prefetched.remove(i).unwrap().1,
+                None => self.prepare(utterance.clone(), self.speed_factor(&queue), voice.clone()),
+            };
+            self.prefetch(&queue, &voice, &mut prefetched);
+
+            let play = async {
+                let prepared = task.await??;
+                tokio::select! {
+                    biased;
+                    _ = queue.interrupted() => {
+                        voice.stop();
+                        Ok(())
+                    }
+                    result = voice.play(prepared) => result,
+                }
+            };
+            tokio::pin!(play);
+            let result = loop {
+                tokio::select! {
+                    result = &mut play => break result,
+                    _ = queue.changed() => self.prefetch(&queue, &voice, &mut prefetched),
+                }
+            };
+            if let Err(why) = result {
+                println!("Failed to play voice: {:?}", why);
+            }
+
+            // The connection was lost while this was being read, so read it
+            // again once reconnected.
+            if queue.is_paused() && !queue.is_closed() {
+                queue.requeue(utterance);
+            }
+            queue.finish();
+        }
+
+        for (_, task) in prefetched {
+            task.abort();
+        }
+    }
+
+    /// How many times as fast to speak with the backlog in the queue.
+    fn speed_factor(&self, queue: &SpeechQueue) -> f64 {
+        if !self.config.adaptive_speed {
+            return 1.0;
+        }
+        (1.0 + SPEED_UP_PER_UTTERANCE * queue.len() as f64).min(self.config.max_speed_up)
+    }
+
+    fn prepare<V>(
+        &'static self,
+        mut utterance: Utterance,
+        speed_factor: f64,
+        voice: Arc<V>,
+    ) -> Prefetch<V::Prepared>
+    where
+        V: Voice + 'static,
+    {
+        tokio::spawn(async move {
+            if speed_factor > 1.0 {
+                utterance.options = utterance.options.sped_up(speed_factor);
+            }
+            let audio = self.synthesize(&utterance).await?;
+            voice.prepare(audio).await
+        })
+    }
+
+    /// Starts preparing the utterances coming up, and stops preparing the ones
+    /// that have been removed from the queue.
+    fn prefetch<V>(
+        &'static self,
+        queue: &SpeechQueue,
+        voice: &Arc<V>,
+        prefetched: &mut VecDeque<(Utterance, Prefetch<V::Prepared>)>,
+    ) where
+        V: Voice + 'static,
+    {
+        let mut stale = std::mem::take(prefetched);
+        for next in queue.upcoming(self.config.prefetch_depth) {
+            let task = match stale.iter().position(|(utterance, _)| *utterance == next) {
+                Some(i) => stale.remove(i).unwrap().1,
+                None => self.prepare(next.clone(), self.speed_factor(queue), voice.clone()),
+            };
+            prefetched.push_back((next, task));
+        }
+        for (_, task) in stale {
+            task.abort();
+        }
+    }
+}
+
+/// The voice of the message, and what is read of it before it is normalized.
+/// A directive is only looked for at the start of what the author wrote, so it
+/// works in replies too.
+fn compose(
+    msg: &ChatMessage,
+    settings: &GuildSettings,
+    saved: tts::Options,
+) -> (tts::Options, String) {
+    let (options, own) = match apply_directive(&msg.text, &saved) {
+        Some((options, own)) => (options, own),
+        None => (saved, msg.text.as_str()),
+    };
+
+    let mut parts = Vec::new();
+    parts.extend(msg.reply.as_deref());
+    parts.push(own);
+    parts.extend(msg.extras.iter().map(String::as_str));
+    if msg.author_is_bot && settings.bot_embeds {
+        parts.extend(msg.embeds.iter().map(String::as_str));
+    }
+    let parts: Vec<_> = parts
+        .into_iter()
+        .filter(|part| !part.trim().is_empty())
+        .collect();
+    (options, parts.join("、"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::option_builder::build_voice_vox_options;
+    use crate::usage::{Quota, QuotaExceeded, Scope};
+
+    /// A guild where the bot reads voice channel 10, summoned from text
+    /// channel 1.
+    struct FakeChat {
+        /// The voice channel the author is in.
+        voice_channel_id: Option<ChannelId>,
+    }
+
+    #[async_trait]
+    impl Chat for FakeChat {
+        fn bot_id(&self) -> UserId {
+            UserId(100)
+        }
+
+        async fn voice_channel_of(&self, _: GuildId, _: UserId) -> Option<ChannelId> {
+            self.voice_channel_id
+        }
+
+        fn reading_channel(&self, _: GuildId) -> Option<ChannelId> {
+            Some(ChannelId(10))
+        }
+
+        fn text_channel(&self, _: GuildId) -> Option<ChannelId> {
+            Some(ChannelId(1))
+        }
+    }
+
+    const LISTENING: FakeChat = FakeChat {
+        voice_channel_id: Some(ChannelId(10)),
+    };
+
+    fn pipeline(quota: Quota) -> Pipeline {
+        Pipeline::new(
+            tts::Client::new(String::new(), String::new()),
+            OptionStorage::in_memory(),
+            UsageStorage::in_memory(quota),
+            GuildSettingsStorage::in_memory(),
+            PipelineConfig::default(),
+        )
+    }
+
+    fn texts(outcome: Outcome) -> Vec<String> {
+        match outcome {
+            Outcome::Read(utterances) => utterances.into_iter().map(|u| u.text).collect(),
+            outcome => panic!("Not read: {:?}", outcome),
+        }
+    }
+
+    fn message(text: &str) -> ChatMessage {
+        ChatMessage {
+            id: MessageId(1),
+            guild_id: GuildId(1),
+            channel_id: ChannelId(1),
+            author_id: UserId(1),
+            author_is_bot: false,
+            author_roles: Vec::new(),
+            is_webhook: false,
+            content: text.to_string(),
+            text: text.to_string(),
+            reply: None,
+            extras: Vec::new(),
+            embeds: Vec::new(),
+            edited: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process() {
+        let pipeline = pipeline(Quota::default());
+        let msg = message("こんにちは");
+        let outcome = pipeline.process(&LISTENING, &msg).await.unwrap();
+        assert_eq!(texts(outcome), ["こんにちは"]);
+
+        // Saying the same again is spam, but editing a message is not.
+        let again = ChatMessage {
+            id: MessageId(2),
+            ..msg.clone()
+        };
+        let outcome = pipeline.process(&LISTENING, &again).await.unwrap();
+        assert_eq!(outcome, Outcome::Throttled);
+        let edited = ChatMessage {
+            edited: true,
+            ..msg.clone()
+        };
+        let outcome = pipeline.process(&LISTENING, &edited).await.unwrap();
+        assert_eq!(texts(outcome), ["こんにちは"]);
+
+        let elsewhere = FakeChat {
+            voice_channel_id: Some(ChannelId(20)),
+        };
+        let outcome = pipeline.process(&elsewhere, &message("もしもし")).await;
+        assert_eq!(outcome.unwrap(), Outcome::Ignored);
+        let own = ChatMessage {
+            author_id: LISTENING.bot_id(),
+            ..message("読み上げ中")
+        };
+        let outcome = pipeline.process(&LISTENING, &own).await;
+        assert_eq!(outcome.unwrap(), Outcome::Ignored);
+        let command = pipeline.process(&LISTENING, &message(".leave")).await;
+        assert_eq!(command.unwrap(), Outcome::Ignored);
+        let english = message("This is not Japanese");
+        let outcome = pipeline.process(&LISTENING, &english).await;
+        assert_eq!(outcome.unwrap(), Outcome::Ignored);
+    }
+
+    #[tokio::test]
+    async fn test_process_ng_words() {
+        let pipeline = pipeline(Quota::default());
+        pipeline
+            .guild_settings()
+            .update(GuildId(1), |settings| {
+                settings.ng_words = vec!["ばか".to_string()];
+                Ok(())
+            })
+            .await
+            .unwrap();
+        // Nothing but NG words is still read, as beeps.
+        let outcome = pipeline.process(&LISTENING, &message("ばか！")).await;
+        assert_eq!(texts(outcome.unwrap()), [format!("{}！", normalize::BEEP)]);
+    }
+
+    #[tokio::test]
+    async fn test_process_quota() {
+        let pipeline = pipeline(Quota {
+            user_daily: Some(10),
+            ..Default::default()
+        });
+        let msg = message("こんにちは");
+        let outcome = pipeline.process(&LISTENING, &msg).await.unwrap();
+        assert_eq!(texts(outcome), ["こんにちは"]);
+
+        pipeline
+            .usage_storage()
+            .record(
+                msg.guild_id,
+                Some(msg.author_id),
+                &tts::Engine::VoiceText,
+                10,
+            )
+            .await
+            .unwrap();
+        let why = pipeline
+            .process(&LISTENING, &message("さようなら"))
+            .await
+            .unwrap_err();
+        let exceeded = why.downcast_ref::<QuotaExceeded>().unwrap();
+        assert_eq!(exceeded.scope, Scope::User(msg.author_id));
+        assert!(exceeded.notify);
+    }
+
+    #[test]
+    fn test_speed_factor() {
+        let adaptive = Pipeline::new(
+            tts::Client::new(String::new(), String::new()),
+            OptionStorage::in_memory(),
+            UsageStorage::in_memory(Quota::default()),
+            GuildSettingsStorage::in_memory(),
+            PipelineConfig {
+                adaptive_speed: true,
+                max_speed_up: 1.5,
+                ..Default::default()
+            },
+        );
+        let utterance = Utterance {
+            text: "あ".to_string(),
+            options: tts::Options::default(),
+            guild_id: GuildId(1),
+            author_id: None,
+            message_id: None,
+        };
+
+        let queue = SpeechQueue::new();
+        assert_eq!(adaptive.speed_factor(&queue), 1.0);
+        queue.extend(vec![utterance.clone(); 3]);
+        assert!((adaptive.speed_factor(&queue) - 1.3).abs() < 1e-9);
+        queue.extend(vec![utterance; 10]);
+        assert_eq!(adaptive.speed_factor(&queue), 1.5);
+
+        assert_eq!(pipeline(Quota::default()).speed_factor(&queue), 1.0);
+    }
+
+    fn text_of(msg: &ChatMessage, settings: &GuildSettings) -> String {
+        compose(msg, settings, tts::Options::default()).1
+    }
+
+    #[test]
+    fn test_text_of_bot_embeds() {
+        let bridged = ChatMessage {
+            author_is_bot: true,
+            embeds: vec!["Steve、ダイヤ見つけた".to_string()],
+            ..message("")
+        };
+        let settings = GuildSettings {
+            bot_embeds: true,
+            ..Default::default()
+        };
+        assert_eq!(text_of(&bridged, &settings), "Steve、ダイヤ見つけた");
+        assert_eq!(text_of(&bridged, &GuildSettings::default()), "");
+
+        let bridged = ChatMessage {
+            text: "[Minecraft]".to_string(),
+            ..bridged
+        };
+        assert_eq!(
+            text_of(&bridged, &settings),
+            "[Minecraft]、Steve、ダイヤ見つけた"
+        );
+
+        // Link previews of people are not read.
+        let linked = ChatMessage {
+            embeds: vec!["プレビュー".to_string()],
+            ..message("見て")
+        };
+        assert_eq!(text_of(&linked, &settings), "見て");
+    }
+
+    #[test]
+    fn test_compose_reply_with_directive() {
+        let settings = GuildSettings::default();
+        let zundamon = tts::Options::VoiceVoxOptions(
+            build_voice_vox_options(["speaker=ずんだもん"].iter()).unwrap(),
+        );
+
+        let reply = ChatMessage {
+            reply: Some("めたんさんへの返信".to_string()),
+            extras: vec!["添付ファイル".to_string()],
+            ..message("{zundamon} やったのだ")
+        };
+        let (options, text) = compose(&reply, &settings, tts::Options::default());
+        assert_eq!(options, zundamon);
+        assert_eq!(text, "めたんさんへの返信、やったのだ、添付ファイル");
+
+        // Only what the author wrote can start with a directive.
+        let reply = ChatMessage {
+            reply: Some("{zundamon}さんへの返信".to_string()),
+            ..message("普通の文")
+        };
+        let (options, text) = compose(&reply, &settings, tts::Options::default());
+        assert_eq!(options, tts::Options::default());
+        assert_eq!(text, "{zundamon}さんへの返信、普通の文");
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serenity::model::id::{GuildId, MessageId, UserId};
+use tokio::sync::Notify;
+
+use crate::tts;
+
+/// A piece of text waiting to be read aloud.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Utterance {
+    pub text: String,
+    pub options: tts::Options,
+    pub guild_id: GuildId,
+    /// `None` for announcements, which aren't made by anyone in the guild.
+    pub author_id: Option<UserId>,
+    /// The message read, which a long one is split into several utterances
+    /// of. `None` for announcements.
+    pub message_id: Option<MessageId>,
+}
+
+/// The utterances of a session, read one at a time by a worker that calls
+/// `next` and `finish` in a loop.
+#[derive(Default)]
+pub struct SpeechQueue {
+    /// The utterances with when they were queued.
+    pending: Mutex<VecDeque<(Instant, Utterance)>>,
+    /// The message of the utterance being read.
+    current: Mutex<Option<MessageId>>,
+    busy: AtomicBool,
+    skipping: AtomicBool,
+    paused: AtomicBool,
+    closed: AtomicBool,
+    changed: Notify,
+}
+
+impl SpeechQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the utterance, returning `false` if the queue has been closed.
+    pub fn push(&self, utterance: Utterance) -> bool {
+        if self.is_closed() {
+            return false;
+        }
+        self.pending.lock().push_back((Instant::now(), utterance));
+        self.changed.notify_waiters();
+        true
+    }
+
+    /// Appends the utterances in a row, returning `false` if the queue has
+    /// been closed.
+    pub fn extend(&self, utterances: impl IntoIterator<Item = Utterance>) -> bool {
+        if self.is_closed() {
+            return false;
+        }
+        let now = Instant::now();
+        self.pending
+            .lock()
+            .extend(utterances.into_iter().map(|utterance| (now, utterance)));
+        self.changed.notify_waiters();
+        true
+    }
+
+    /// The first `n` utterances to be read, unless the queue changes before
+    /// then.
+    pub fn upcoming(&self, n: usize) -> Vec<Utterance> {
+        self.pending
+            .lock()
+            .iter()
+            .take(n)
+            .map(|(_, utterance)| utterance.clone())
+            .collect()
+    }
+
+    /// Waits for the queue to change in any way.
+    pub async fn changed(&self) {
+        self.changed.notified().await;
+    }
+
+    /// Waits for the next utterance. Returns `None` once the queue is closed.
+    pub async fn next(&self) -> Option<Utterance> {
+        loop {
+            let changed = self.changed.notified();
+            if self.is_closed() {
+                return None;
+            }
+            if !self.is_paused() {
+                // `busy` is set under the lock so that `is_idle` never sees the
+                // utterance in neither place.
+                let mut pending = self.pending.lock();
+                if let Some((_, utterance)) = pending.pop_front() {
+                    *self.current.lock() = utterance.message_id;
+                    self.busy.store(true, Ordering::SeqCst);
+                    self.skipping.store(false, Ordering::SeqCst);
+                    return Some(utterance);
+                }
+            }
+            changed.await;
+        }
+    }
+
+    /// Tells that the utterance returned by `next` has been dealt with.
+    pub fn finish(&self) {
+        *self.current.lock() = None;
+        self.busy.store(false, Ordering::SeqCst);
+        self.changed.notify_waiters();
+    }
+
+    /// Waits until the utterance being read should be cut off because of
+    /// `skip`, `pause` or `close`.
+    pub async fn interrupted(&self) {
+        loop {
+            let changed = self.changed.notified();
+            if self.skipping.load(Ordering::SeqCst) || self.is_paused() || self.is_closed() {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.lock().is_empty()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        let pending = self.pending.lock();
+        pending.is_empty() && !self.busy.load(Ordering::SeqCst)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Waits until nothing is pending or being read.
+    pub async fn wait_idle(&self) {
+        loop {
+            let changed = self.changed.notified();
+            if self.is_idle() || self.is_closed() {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    /// Puts the utterance back at the head of the queue, e.g. when it was cut
+    /// off by a lost voice connection.
+    pub fn requeue(&self, utterance: Utterance) {
+        self.pending.lock().push_front((Instant::now(), utterance));
+        self.changed.notify_waiters();
+    }
+
+    /// Holds the utterances until `resume` is called. The one being read is
+    /// cut off.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+        self.changed.notify_waiters();
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.changed.notify_waiters();
+    }
+
+    /// Cuts off the utterance being read, if any.
+    pub fn skip(&self) {
+        if self.busy.load(Ordering::SeqCst) {
+            self.skipping.store(true, Ordering::SeqCst);
+            self.changed.notify_waiters();
+        }
+    }
+
+    /// Drops the utterances queued more than `max_lag` ago, returning how many
+    /// were dropped.
+    pub fn drop_stale(&self, max_lag: Duration) -> usize {
+        let mut pending = self.pending.lock();
+        let len = pending.len();
+        pending.retain(|(queued_at, _)| queued_at.elapsed() <= max_lag);
+        let dropped = len - pending.len();
+        if dropped > 0 {
+            self.changed.notify_waiters();
+        }
+        dropped
+    }
+
+    /// Drops the utterances of the message, cutting it off if it is being
+    /// read. Returns whether there were any.
+    pub fn cancel(&self, message_id: MessageId) -> bool {
+        let mut pending = self.pending.lock();
+        let len = pending.len();
+        pending.retain(|(_, utterance)| utterance.message_id != Some(message_id));
+        let mut found = pending.len() != len;
+        if *self.current.lock() == Some(message_id) {
+            self.skipping.store(true, Ordering::SeqCst);
+            found = true;
+        }
+        drop(pending);
+        if found {
+            self.changed.notify_waiters();
+        }
+        found
+    }
+
+    /// Whether the message has utterances queued and hasn't started being read.
+    pub fn is_waiting(&self, message_id: MessageId) -> bool {
+        let pending = self.pending.lock();
+        *self.current.lock() != Some(message_id)
+            && pending
+                .iter()
+                .any(|(_, utterance)| utterance.message_id == Some(message_id))
+    }
+
+    /// Puts the utterances in place of the ones of the same message, unless it
+    /// has started being read. Returns whether they were replaced.
+    pub fn replace(&self, message_id: MessageId, utterances: Vec<Utterance>) -> bool {
+        let mut pending = self.pending.lock();
+        if *self.current.lock() == Some(message_id) {
+            return false;
+        }
+        let (first, queued_at) = match pending
+            .iter()
+            .position(|(_, utterance)| utterance.message_id == Some(message_id))
+        {
+            Some(i) => (i, pending[i].0),
+            None => return false,
+        };
+        pending.retain(|(_, utterance)| utterance.message_id != Some(message_id));
+        for (i, utterance) in utterances.into_iter().enumerate() {
+            pending.insert(first + i, (queued_at, utterance));
+        }
+        drop(pending);
+        self.changed.notify_waiters();
+        true
+    }
+
+    /// Drops all the pending utterances.
+    pub fn clear(&self) {
+        self.pending.lock().clear();
+        self.changed.notify_waiters();
+    }
+
+    /// Cancels everything and makes the worker stop.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::tts::voice_text::VoiceTextOptionsBuilder;
+
+    fn utterance(text: &str) -> Utterance {
+        Utterance {
+            text: text.to_string(),
+            options: tts::Options::VoiceTextOptions(
+                VoiceTextOptionsBuilder::default()
+                    .speaker("show".try_into().unwrap())
+                    .build()
+                    .unwrap(),
+            ),
+            guild_id: GuildId(1),
+            author_id: Some(UserId(1)),
+            message_id: None,
+        }
+    }
+
+    fn message(id: u64, text: &str) -> Utterance {
+        Utterance {
+            message_id: Some(MessageId(id)),
+            ..utterance(text)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let queue = SpeechQueue::new();
+        queue.push(utterance("a"));
+        queue.push(utterance("b"));
+        assert!(!queue.is_idle());
+
+        assert_eq!(queue.next().await.unwrap().text, "a");
+        queue.pause();
+        // Cut off by the pause, so "a" is read again after `resume`.
+        queue.interrupted().await;
+        queue.requeue(utterance("a"));
+        queue.finish();
+        queue.resume();
+        assert_eq!(queue.next().await.unwrap().text, "a");
+        queue.finish();
+        assert_eq!(queue.next().await.unwrap().text, "b");
+        assert!(!queue.is_idle());
+        queue.finish();
+        assert!(queue.is_idle());
+
+        queue.close();
+        assert!(!queue.push(utterance("c")));
+        assert!(queue.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_and_replace() {
+        let queue = SpeechQueue::new();
+        queue.extend([message(1, "a1"), message(1, "a2"), message(2, "b")]);
+        assert!(queue.is_waiting(MessageId(1)));
+        assert!(!queue.is_waiting(MessageId(3)));
+
+        assert!(queue.replace(MessageId(2), vec![message(2, "c")]));
+        assert_eq!(queue.next().await.unwrap().text, "a1");
+        // "a" has started being read.
+        assert!(!queue.is_waiting(MessageId(1)));
+        assert!(!queue.replace(MessageId(1), vec![message(1, "d")]));
+        assert!(queue.cancel(MessageId(1)));
+        queue.interrupted().await;
+        queue.finish();
+        assert_eq!(queue.next().await.unwrap().text, "c");
+        queue.finish();
+        assert!(!queue.cancel(MessageId(1)));
+    }
+
+    #[test]
+    fn test_drop_stale() {
+        let queue = SpeechQueue::new();
+        queue.push(utterance("a"));
+        assert_eq!(queue.drop_stale(Duration::from_secs(60)), 0);
+        assert_eq!(queue.drop_stale(Duration::ZERO), 1);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serenity::model::id::{ChannelId, RoleId};
+
+/// Timestamps are read in Japan Standard Time, where the listeners are.
+const UTC_OFFSET: i64 = 9 * 60 * 60;
+
+static MARKUP: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(concat!(
+        r"<t:(?P<time>-?\d+)(?::(?P<style>[tTdDfFR]))?>",
+        r"|<#(?P<channel>\d+)>",
+        r"|<@&(?P<role>\d+)>",
+        r"|<a?:(?P<emoji>\w+):\d+>",
+    ))
+    .unwrap()
+});
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "bmp", "heic"];
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mov", "webm", "mkv", "avi"];
+
+/// Looks up the names of what a message mentions.
+pub trait Names {
+    fn channel_name(&self, channel_id: ChannelId) -> Option<String>;
+
+    fn role_name(&self, role_id: RoleId) -> Option<String>;
+}
+
+/// Rewrites the Discord markup in the content into what can be read: channel
+/// and role mentions into their names, timestamps into dates or relative times,
+/// and custom emoji into their names. `now` is the current Unix time.
+pub fn render(content: &str, names: &impl Names, now: i64) -> String {
+    MARKUP
+        .replace_all(content, |caps: &Captures| {
+            if let Some(time) = caps.name("time") {
+                let style = caps.name("style").map_or("f", |style| style.as_str());
+                match time.as_str().parse() {
+                    Ok(time) => timestamp(time, style, now),
+                    Err(_) => caps[0].to_string(),
+                }
+            } else if let Some(channel) = caps.name("channel") {
+                channel
+                    .as_str()
+                    .parse()
+                    .ok()
+                    .and_then(|id| names.channel_name(ChannelId(id)))
+                    .unwrap_or_else(|| "不明なチャンネル".to_string())
+            } else if let Some(role) = caps.name("role") {
+                role.as_str()
+                    .parse()
+                    .ok()
+                    .and_then(|id| names.role_name(RoleId(id)))
+                    .unwrap_or_else(|| "不明なロール".to_string())
+            } else {
+                caps["emoji"].replace('_', " ")
+            }
+        })
+        .into_owned()
+}
+
+/// How an attachment is read, by the kind of file it is.
+pub fn attachment(filename: &str) -> String {
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_lowercase())
+        .unwrap_or_default();
+    if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        "画像ファイル".to_string()
+    } else if VIDEO_EXTENSIONS.contains(&extension.as_str()) {
+        "動画ファイル".to_string()
+    } else {
+        filename.to_string()
+    }
+}
+
+pub fn sticker(name: &str) -> String {
+    format!("スタンプ: {}", name)
+}
+
+/// Reads a timestamp in one of the styles of Discord.
+fn timestamp(time: i64, style: &str, now: i64) -> String {
+    if style == "R" {
+        return relative(time.saturating_sub(now));
+    }
+
+    let local = time.saturating_add(UTC_OFFSET);
+    let days = local.div_euclid(86400);
+    let seconds = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    let (hour, minute, second) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    // 1970-01-01 was a Thursday.
+    let weekday = ["日", "月", "火", "水", "木", "金", "土"][(days + 4).rem_euclid(7) as usize];
+
+    let date = format!("{}年{}月{}日", year, month, day);
+    let time = format!("{}時{}分", hour, minute);
+    match style {
+        "t" => time,
+        "T" => format!("{}{}秒", time, second),
+        "d" | "D" => date,
+        "F" => format!("{}{}曜日 {}", date, weekday, time),
+        _ => format!("{} {}", date, time),
+    }
+}
+
+/// Reads a time difference in seconds, as in "3分前" or "2日後".
+fn relative(difference: i64) -> String {
+    let seconds = difference.unsigned_abs();
+    if seconds == 0 {
+        return "今".to_string();
+    }
+    let (amount, unit) = match seconds {
+        s if s < 60 => (s, "秒"),
+        s if s < 60 * 60 => (s / 60, "分"),
+        s if s < 24 * 60 * 60 => (s / (60 * 60), "時間"),
+        s if s < 30 * 24 * 60 * 60 => (s / (24 * 60 * 60), "日"),
+        s if s < 365 * 24 * 60 * 60 => (s / (30 * 24 * 60 * 60), "か月"),
+        s => (s / (365 * 24 * 60 * 60), "年"),
+    };
+    format!(
+        "{}{}{}",
+        amount,
+        unit,
+        if difference > 0 { "後" } else { "前" }
+    )
+}
+
+/// The date of the days since 1970-01-01, as in
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct TestNames;
+
+    impl Names for TestNames {
+        fn channel_name(&self, channel_id: ChannelId) -> Option<String> {
+            (channel_id == ChannelId(1)).then(|| "雑談".to_string())
+        }
+
+        fn role_name(&self, role_id: RoleId) -> Option<String> {
+            (role_id == RoleId(2)).then(|| "運営".to_string())
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        // 2023-11-14 22:13:20 UTC, a Tuesday.
+        let time = 1700000000;
+        assert_eq!(
+            render("<#1>で<@&2>が話す", &TestNames, time),
+            "雑談で運営が話す"
+        );
+        assert_eq!(render("<#3>", &TestNames, time), "不明なチャンネル");
+        assert_eq!(
+            render("<t:1700000000:F>", &TestNames, time),
+            "2023年11月15日水曜日 7時13分"
+        );
+        assert_eq!(render("<t:1700000000:t>", &TestNames, time), "7時13分");
+        assert_eq!(
+            render("<t:1700000000>", &TestNames, time),
+            "2023年11月15日 7時13分"
+        );
+        assert_eq!(render("<t:1700000000:R>", &TestNames, time - 180), "3分後");
+        assert_eq!(
+            render("<t:1700000000:R>", &TestNames, time + 2 * 86400),
+            "2日前"
+        );
+        assert_eq!(
+            render("<:party_parrot:123>", &TestNames, time),
+            "party parrot"
+        );
+    }
+
+    #[test]
+    fn test_attachment() {
+        assert_eq!(attachment("IMG_0001.JPG"), "画像ファイル");
+        assert_eq!(attachment("clip.mp4"), "動画ファイル");
+        assert_eq!(attachment("notes.txt"), "notes.txt");
+    }
+}
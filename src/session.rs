@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use sqlx::mysql::MySqlPool;
+
+/// A bot account reading a voice channel. The text channel is the one `.join`
+/// was run in, where the bot posts its notices.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Session {
+    pub bot_id: UserId,
+    pub voice_channel_id: ChannelId,
+    pub text_channel_id: ChannelId,
+}
+
+/// Keeps track of which bot account is in which voice channel. A guild can
+/// have as many sessions as there are bot accounts in it.
+#[derive(Debug, Default)]
+pub struct SessionRegistry {
+    sessions: HashMap<GuildId, Vec<Session>>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds the bot to the voice channel, replacing its previous binding in
+    /// the guild if any.
+    pub fn bind(&mut self, guild_id: GuildId, session: Session) {
+        let sessions = self.sessions.entry(guild_id).or_default();
+        sessions.retain(|s| s.bot_id != session.bot_id);
+        sessions.push(session);
+    }
+
+    pub fn unbind(&mut self, guild_id: GuildId, bot_id: UserId) -> Option<Session> {
+        let sessions = self.sessions.get_mut(&guild_id)?;
+        let index = sessions.iter().position(|s| s.bot_id == bot_id)?;
+        let session = sessions.remove(index);
+        if sessions.is_empty() {
+            self.sessions.remove(&guild_id);
+        }
+        Some(session)
+    }
+
+    pub fn find_by_channel(
+        &self,
+        guild_id: GuildId,
+        voice_channel_id: ChannelId,
+    ) -> Option<Session> {
+        self.sessions
+            .get(&guild_id)?
+            .iter()
+            .find(|s| s.voice_channel_id == voice_channel_id)
+            .copied()
+    }
+
+    pub fn find_by_bot(&self, guild_id: GuildId, bot_id: UserId) -> Option<Session> {
+        self.sessions
+            .get(&guild_id)?
+            .iter()
+            .find(|s| s.bot_id == bot_id)
+            .copied()
+    }
+
+    pub fn sessions_in(&self, guild_id: GuildId) -> &[Session] {
+        self.sessions
+            .get(&guild_id)
+            .map(|s| s.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns the bots among `bot_ids` that are not in any voice channel of
+    /// the guild, in the given order.
+    pub fn idle_bots<I>(&self, guild_id: GuildId, bot_ids: I) -> Vec<UserId>
+    where
+        I: IntoIterator<Item = UserId>,
+    {
+        let sessions = self.sessions_in(guild_id);
+        bot_ids
+            .into_iter()
+            .filter(|bot_id| sessions.iter().all(|s| s.bot_id != *bot_id))
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (GuildId, Session)> + '_ {
+        self.sessions
+            .iter()
+            .flat_map(|(guild_id, sessions)| sessions.iter().map(|s| (*guild_id, *s)))
+    }
+}
+
+/// Persists sessions so that they survive restarts.
+pub struct SessionStorage {
+    pool: MySqlPool,
+}
+
+impl SessionStorage {
+    pub fn new(pool: MySqlPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn load(&self, bot_id: &UserId) -> anyhow::Result<Vec<(GuildId, Session)>> {
+        let records = sqlx::query!(
+            r#"
+SELECT guild_id, voice_channel_id, text_channel_id
+FROM sessions
+WHERE bot_id = ?
+            "#,
+            bot_id.0
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(records
+            .into_iter()
+            .map(|r| {
+                (
+                    GuildId(r.guild_id),
+                    Session {
+                        bot_id: *bot_id,
+                        voice_channel_id: ChannelId(r.voice_channel_id),
+                        text_channel_id: ChannelId(r.text_channel_id),
+                    },
+                )
+            })
+            .collect())
+    }
+
+    pub async fn save(&self, guild_id: &GuildId, session: &Session) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+REPLACE INTO sessions (guild_id, bot_id, voice_channel_id, text_channel_id)
+VALUES (?, ?, ?, ?)
+            "#,
+            guild_id.0,
+            session.bot_id.0,
+            session.voice_channel_id.0,
+            session.text_channel_id.0
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, guild_id: &GuildId, bot_id: &UserId) -> anyhow::Result<()> {
+        sqlx::query!(
+            "DELETE FROM sessions WHERE guild_id = ? AND bot_id = ?",
+            guild_id.0,
+            bot_id.0
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_registry() {
+        let guild_id = GuildId(1);
+        let mut registry = SessionRegistry::new();
+        registry.bind(
+            guild_id,
+            Session {
+                bot_id: UserId(10),
+                voice_channel_id: ChannelId(100),
+                text_channel_id: ChannelId(1000),
+            },
+        );
+        assert_eq!(
+            registry.idle_bots(guild_id, [UserId(10), UserId(11), UserId(12)]),
+            vec![UserId(11), UserId(12)]
+        );
+
+        // Moving a bot replaces its binding instead of adding another one.
+        registry.bind(
+            guild_id,
+            Session {
+                bot_id: UserId(10),
+                voice_channel_id: ChannelId(101),
+                text_channel_id: ChannelId(1000),
+            },
+        );
+        assert_eq!(registry.sessions_in(guild_id).len(), 1);
+        assert_eq!(registry.find_by_channel(guild_id, ChannelId(100)), None);
+        assert_eq!(
+            registry
+                .find_by_channel(guild_id, ChannelId(101))
+                .unwrap()
+                .bot_id,
+            UserId(10)
+        );
+
+        assert!(registry.unbind(guild_id, UserId(10)).is_some());
+        assert!(registry.unbind(guild_id, UserId(10)).is_none());
+        assert_eq!(registry.iter().count(), 0);
+    }
+}
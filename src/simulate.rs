@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serenity::async_trait;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::pipeline::{Chat, ChatMessage, Outcome, Pipeline, Voice};
+use crate::queue::SpeechQueue;
+
+/// A chat where the user is always in the voice channel the bot reads.
+struct SimulatedChat;
+
+#[async_trait]
+impl Chat for SimulatedChat {
+    fn bot_id(&self) -> UserId {
+        UserId(0)
+    }
+
+    async fn voice_channel_of(&self, _: GuildId, _: UserId) -> Option<ChannelId> {
+        Some(ChannelId(0))
+    }
+
+    fn reading_channel(&self, _: GuildId) -> Option<ChannelId> {
+        Some(ChannelId(0))
+    }
+
+    fn text_channel(&self, _: GuildId) -> Option<ChannelId> {
+        Some(ChannelId(0))
+    }
+}
+
+/// Writes the audio to numbered files instead of playing it.
+struct FileVoice {
+    output_dir: PathBuf,
+    count: AtomicUsize,
+}
+
+#[async_trait]
+impl Voice for FileVoice {
+    type Prepared = Vec<u8>;
+
+    async fn prepare(&self, audio: Vec<u8>) -> anyhow::Result<Self::Prepared> {
+        Ok(audio)
+    }
+
+    async fn play(&self, audio: Vec<u8>) -> anyhow::Result<()> {
+        let count = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+        let path = self.output_dir.join(format!("{:04}.wav", count));
+        tokio::fs::write(&path, audio).await?;
+        println!("{}", path.display());
+        Ok(())
+    }
+
+    fn stop(&self) {}
+}
+
+/// Runs each line of `input` through the pipeline as a message of the user,
+/// writing what would be played to `output_dir` and telling why each of the
+/// others is not read.
+pub async fn simulate<R>(
+    pipeline: &'static Pipeline,
+    guild_id: GuildId,
+    user_id: UserId,
+    input: R,
+    output_dir: &Path,
+) -> anyhow::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+{
+    tokio::fs::create_dir_all(output_dir).await?;
+
+    let queue = Arc::new(SpeechQueue::new());
+    let worker = tokio::spawn(pipeline.speak(
+        queue.clone(),
+        FileVoice {
+            output_dir: output_dir.to_path_buf(),
+            count: AtomicUsize::new(0),
+        },
+    ));
+
+    let mut lines = input.lines();
+    let mut count = 0;
+    while let Some(line) = lines.next_line().await? {
+        count += 1;
+        let msg = ChatMessage {
+            id: MessageId(count),
+            guild_id,
+            channel_id: ChannelId(0),
+            author_id: user_id,
+            author_is_bot: false,
+            author_roles: Vec::new(),
+            is_webhook: false,
+            content: line.clone(),
+            text: line,
+            reply: None,
+            extras: Vec::new(),
+            embeds: Vec::new(),
+            edited: false,
+        };
+        match pipeline.process(&SimulatedChat, &msg).await {
+            Ok(Outcome::Read(utterances)) => {
+                queue.extend(utterances);
+            }
+            Ok(Outcome::Ignored) => eprintln!("Ignored: {}", msg.content),
+            Ok(Outcome::Throttled) => eprintln!("Throttled: {}", msg.content),
+            // Refused for the quota, or failed, which doesn't stop the rest
+            // from being simulated.
+            Err(why) => eprintln!("Not read: {}: {:#}", msg.content, why),
+        }
+    }
+
+    queue.wait_idle().await;
+    queue.close();
+    worker.await?;
+    Ok(())
+}
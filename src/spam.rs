@@ -0,0 +1,122 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serenity::model::id::{GuildId, UserId};
+
+/// Limits the messages and characters each user can have read in a sliding
+/// window.
+#[derive(Debug)]
+pub struct RateLimiter {
+    window: Duration,
+    max_messages: Option<usize>,
+    max_characters: Option<usize>,
+    history: Mutex<HashMap<UserId, VecDeque<(Instant, usize)>>>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        window: Duration,
+        max_messages: Option<usize>,
+        max_characters: Option<usize>,
+    ) -> Self {
+        Self {
+            window,
+            max_messages,
+            max_characters,
+            history: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts the message if it is within the limits, returning whether it is.
+    pub fn try_acquire(&self, user_id: UserId, characters: usize) -> bool {
+        if self.max_messages.is_none() && self.max_characters.is_none() {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut history = self.history.lock();
+        for entries in history.values_mut() {
+            while matches!(entries.front(), Some((at, _)) if now.duration_since(*at) >= self.window)
+            {
+                entries.pop_front();
+            }
+        }
+        history.retain(|_, entries| !entries.is_empty());
+
+        let entries = history.entry(user_id).or_default();
+        if self.max_messages.map_or(false, |max| entries.len() >= max) {
+            return false;
+        }
+        let total: usize = entries.iter().map(|(_, characters)| characters).sum();
+        if self
+            .max_characters
+            .map_or(false, |max| total + characters > max)
+        {
+            return false;
+        }
+        entries.push_back((now, characters));
+        true
+    }
+}
+
+/// Drops a message identical to the one read just before it in the session,
+/// so that a pasted line or a copypasta chain is read once.
+#[derive(Debug)]
+pub struct RepeatFilter {
+    window: Duration,
+    last: Mutex<HashMap<(GuildId, UserId), (String, Instant)>>,
+}
+
+impl RepeatFilter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_repeat(&self, guild_id: GuildId, bot_id: UserId, text: &str) -> bool {
+        matches!(
+            self.last.lock().get(&(guild_id, bot_id)),
+            Some((last, at)) if last == text && at.elapsed() < self.window
+        )
+    }
+
+    pub fn remember(&self, guild_id: GuildId, bot_id: UserId, text: &str) {
+        self.last
+            .lock()
+            .insert((guild_id, bot_id), (text.to_string(), Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(Duration::from_secs(60), Some(2), Some(10));
+        assert!(limiter.try_acquire(UserId(1), 3));
+        assert!(limiter.try_acquire(UserId(1), 3));
+        // Too many messages.
+        assert!(!limiter.try_acquire(UserId(1), 1));
+        // Too many characters.
+        assert!(!limiter.try_acquire(UserId(2), 11));
+        assert!(limiter.try_acquire(UserId(2), 10));
+
+        let limiter = RateLimiter::new(Duration::ZERO, Some(1), None);
+        assert!(limiter.try_acquire(UserId(1), 1));
+        assert!(limiter.try_acquire(UserId(1), 1));
+    }
+
+    #[test]
+    fn test_repeat_filter() {
+        let filter = RepeatFilter::new(Duration::from_secs(60));
+        assert!(!filter.is_repeat(GuildId(1), UserId(1), "a"));
+        filter.remember(GuildId(1), UserId(1), "a");
+        assert!(filter.is_repeat(GuildId(1), UserId(1), "a"));
+        assert!(!filter.is_repeat(GuildId(1), UserId(1), "b"));
+        assert!(!filter.is_repeat(GuildId(2), UserId(1), "a"));
+    }
+}
@@ -7,10 +7,12 @@ use std::fmt;
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumIter, EnumString};
 
-use self::voice_text::{VoiceTextClient, VoiceTextOptions, VoiceTextOptionsBuilder};
-use self::voice_vox::{VoiceVoxClient, VoiceVoxOptions, VoiceVoxOptionsBuilder};
+use self::voice_text::{
+    VoiceTextClient, VoiceTextFormat, VoiceTextOptions, VoiceTextOptionsBuilder, VoiceTextSpeaker,
+};
+use self::voice_vox::{VoiceVoxClient, VoiceVoxOptions};
 
-#[derive(Display, EnumIter, EnumString)]
+#[derive(Clone, Copy, Debug, Display, EnumIter, EnumString, Eq, Hash, PartialEq)]
 #[strum(serialize_all = "lowercase")]
 pub enum Engine {
     VoiceText,
@@ -24,12 +26,53 @@ pub enum Preset {
     Munou,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum Options {
     VoiceTextOptions(VoiceTextOptions),
     VoiceVoxOptions(VoiceVoxOptions),
 }
 
+impl Options {
+    pub fn engine(&self) -> Engine {
+        match self {
+            Options::VoiceTextOptions(_) => Engine::VoiceText,
+            Options::VoiceVoxOptions(_) => Engine::VoiceVox,
+        }
+    }
+
+    /// The options as the `key=value` arguments of `build_options`.
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            Options::VoiceTextOptions(options) => options.args(),
+            Options::VoiceVoxOptions(options) => options.args(),
+        }
+    }
+
+    pub fn sped_up(&self, factor: f64) -> Self {
+        match self {
+            Options::VoiceTextOptions(options) => {
+                Options::VoiceTextOptions(options.sped_up(factor))
+            }
+            Options::VoiceVoxOptions(options) => Options::VoiceVoxOptions(options.sped_up(factor)),
+        }
+    }
+}
+
+impl Default for Options {
+    /// The voice of users who have never chosen one.
+    fn default() -> Self {
+        Options::VoiceTextOptions(VoiceTextOptions {
+            speaker: VoiceTextSpeaker::Show,
+            format: VoiceTextFormat::Wav,
+            emotion: None,
+            emotion_level: 2,
+            pitch: 100,
+            speed: 100,
+            volume: 100,
+        })
+    }
+}
+
 impl From<Preset> for Options {
     fn from(preset: Preset) -> Self {
         match preset {
@@ -71,11 +114,36 @@ impl Client {
     ) -> anyhow::Result<Vec<u8>> {
         match options {
             Options::VoiceTextOptions(options) => {
-                self.voice_text_client.request(text, &options).await
-            }
-            Options::VoiceVoxOptions(options) => {
-                self.voice_vox_client.request(text, &options).await
+                self.voice_text_client.request(text, options).await
             }
+            Options::VoiceVoxOptions(options) => self.voice_vox_client.request(text, options).await,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use self::voice_vox::VoiceVoxOptionsBuilder;
+
+    #[test]
+    fn test_sped_up() {
+        let options = Options::default().sped_up(1.5);
+        assert!(matches!(options, Options::VoiceTextOptions(ref o) if o.speed == 150));
+        // Up to the fastest the engine allows.
+        let options = options.sped_up(3.0);
+        assert!(matches!(options, Options::VoiceTextOptions(ref o) if o.speed == 400));
+
+        let voice_vox = |speed: f64| {
+            let options = VoiceVoxOptionsBuilder::default()
+                .speaker("ずんだもん".try_into().unwrap())
+                .speed(speed)
+                .build()
+                .unwrap();
+            Options::VoiceVoxOptions(options)
+        };
+        assert_eq!(voice_vox(1.0).sped_up(1.5), voice_vox(1.5));
+        assert_eq!(voice_vox(1.0).sped_up(3.0), voice_vox(2.0));
+    }
+}
@@ -41,7 +41,8 @@ impl VoiceTextClient {
             .basic_auth(&self.api_key, None as Option<&str>)
             .form(&params)
             .send()
-            .await?;
+            .await?
+            .error_for_status()?;
         Ok(resp.bytes().await?.to_vec())
     }
 }
@@ -64,6 +65,33 @@ pub struct VoiceTextOptions {
     pub volume: u8,
 }
 
+impl VoiceTextOptions {
+    const MAX_SPEED: u16 = 400;
+
+    /// The options as the `key=value` arguments of `build_voice_text_options`.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            format!("speaker={}", self.speaker),
+            format!("emotion_level={}", self.emotion_level),
+            format!("pitch={}", self.pitch),
+            format!("speed={}", self.speed),
+        ];
+        if let Some(emotion) = &self.emotion {
+            args.push(format!("emotion={}", emotion));
+        }
+        args
+    }
+
+    /// The options with the speech `factor` times as fast, up to the fastest
+    /// the engine allows.
+    pub fn sped_up(&self, factor: f64) -> Self {
+        Self {
+            speed: ((self.speed as f64 * factor).round() as u16).min(Self::MAX_SPEED),
+            ..self.clone()
+        }
+    }
+}
+
 impl VoiceTextOptionsBuilder {
     fn validate(&self) -> Result<(), String> {
         if let Some(ref emotion) = self.emotion {
@@ -78,25 +106,25 @@ impl VoiceTextOptionsBuilder {
         }
 
         if let Some(emotion_level) = self.emotion_level {
-            if emotion_level < 1 || emotion_level > 4 {
+            if !(1..=4).contains(&emotion_level) {
                 return Err("Bad emotion_level, must be 1 <= emotion_level <= 4".to_string());
             }
         }
 
         if let Some(pitch) = self.pitch {
-            if pitch < 50 || pitch > 200 {
+            if !(50..=200).contains(&pitch) {
                 return Err("Bad pitch, must be 50 <= pitch <= 200".to_string());
             }
         }
 
         if let Some(speed) = self.speed {
-            if speed < 50 || speed > 400 {
+            if !(50..=400).contains(&speed) {
                 return Err("Bad speed, must be 50 <= speed <= 400".to_string());
             }
         }
 
         if let Some(volume) = self.volume {
-            if volume < 50 || volume > 200 {
+            if !(50..=200).contains(&volume) {
                 return Err("Bad volume, must be 50 <= volume <= 200".to_string());
             }
         }
@@ -36,12 +36,14 @@ impl VoiceVoxClient {
             .get("https://api.su-shiki.com/v2/voicevox/audio")
             .query(&query)
             .send()
-            .await?;
+            .await?
+            .error_for_status()?;
         Ok(resp.bytes().await?.to_vec())
     }
 }
 
 #[derive(Builder, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[builder(build_fn(validate = "Self::validate"))]
 pub struct VoiceVoxOptions {
     speaker: VoiceVoxSpeaker,
     #[builder(default = "0.0")]
@@ -52,6 +54,53 @@ pub struct VoiceVoxOptions {
     speed: f64,
 }
 
+impl VoiceVoxOptions {
+    const MAX_SPEED: f64 = 2.0;
+
+    /// The options as the `key=value` arguments of `build_voice_vox_options`.
+    pub fn args(&self) -> Vec<String> {
+        vec![
+            format!("speaker={}", self.speaker),
+            format!("pitch={}", self.pitch),
+            format!("intonationScale={}", self.intonation_scale),
+            format!("speed={}", self.speed),
+        ]
+    }
+
+    /// The options with the speech `factor` times as fast, up to the fastest
+    /// the engine allows.
+    pub fn sped_up(&self, factor: f64) -> Self {
+        Self {
+            speed: (self.speed * factor).min(Self::MAX_SPEED.max(self.speed)),
+            ..self.clone()
+        }
+    }
+}
+
+impl VoiceVoxOptionsBuilder {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(pitch) = self.pitch {
+            if !(-0.15..=0.15).contains(&pitch) {
+                return Err("Bad pitch, must be -0.15 <= pitch <= 0.15".to_string());
+            }
+        }
+
+        if let Some(intonation_scale) = self.intonation_scale {
+            if !(0.0..=2.0).contains(&intonation_scale) {
+                return Err("Bad intonationScale, must be 0 <= intonationScale <= 2".to_string());
+            }
+        }
+
+        if let Some(speed) = self.speed {
+            if !(0.5..=VoiceVoxOptions::MAX_SPEED).contains(&speed) {
+                return Err("Bad speed, must be 0.5 <= speed <= 2".to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Display, EnumIter, EnumString, PartialEq, Serialize)]
 pub enum VoiceVoxSpeaker {
     四国めたん = 2,
@@ -88,4 +137,30 @@ mod test {
         );
         assert_eq!(VoiceVoxSpeaker::九州そら as u8, 16);
     }
+
+    #[test]
+    fn test_builder() {
+        let speaker = || VoiceVoxSpeaker::try_from("ずんだもん").unwrap();
+        let opt = VoiceVoxOptionsBuilder::default()
+            .speaker(speaker())
+            .pitch(0.15)
+            .intonation_scale(0.0)
+            .speed(2.0)
+            .build()
+            .unwrap();
+        assert_eq!(opt.args()[3], "speed=2");
+
+        let err = VoiceVoxOptionsBuilder::default()
+            .speaker(speaker())
+            .speed(150.0)
+            .build()
+            .unwrap_err();
+        assert_eq!(&err.to_string(), "Bad speed, must be 0.5 <= speed <= 2");
+
+        assert!(VoiceVoxOptionsBuilder::default()
+            .speaker(speaker())
+            .pitch(f64::NAN)
+            .build()
+            .is_err());
+    }
 }
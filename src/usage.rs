@@ -0,0 +1,374 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use serenity::model::id::{GuildId, UserId};
+use sqlx::mysql::MySqlPool;
+
+use crate::cache::TtlCache;
+use crate::guild_settings::GuildSettings;
+use crate::tts;
+
+/// How long totals are trusted before being summed up in the database again,
+/// which is also how late the usage of other bot processes shows up.
+const USAGE_CACHE_TTL: Duration = Duration::from_secs(60);
+const USAGE_CACHE_CAPACITY: usize = 10_000;
+
+/// How long to stay quiet after telling that a quota has been used up.
+const NOTICE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Guild(GuildId),
+    /// A user across all guilds.
+    User(UserId),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Period {
+    /// Since midnight in the database's time zone.
+    Day,
+    /// Since the first of the month in the database's time zone.
+    Month,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Totals {
+    pub day: u64,
+    pub month: u64,
+}
+
+impl Totals {
+    pub fn get(&self, period: Period) -> u64 {
+        match period {
+            Period::Day => self.day,
+            Period::Month => self.month,
+        }
+    }
+}
+
+/// Characters synthesized, by engine name.
+pub type Usage = BTreeMap<String, Totals>;
+
+/// Limits on the characters synthesized with engines that are paid for. Those
+/// of guilds are defaults, which each guild's own settings can replace.
+#[derive(Clone, Debug, Default)]
+pub struct Quota {
+    pub guild_daily: Option<u64>,
+    pub guild_monthly: Option<u64>,
+    pub user_daily: Option<u64>,
+    pub user_monthly: Option<u64>,
+    /// The voice used once a quota has been used up, instead of refusing to
+    /// read. Its engine is assumed to be free, so it's not metered.
+    pub fallback: Option<tts::Options>,
+}
+
+impl Quota {
+    /// The quota in the guild, with the limits it has set in place of the
+    /// defaults.
+    pub fn for_guild(&self, settings: &GuildSettings) -> Quota {
+        Quota {
+            guild_daily: settings.daily_quota.or(self.guild_daily),
+            guild_monthly: settings.monthly_quota.or(self.guild_monthly),
+            ..self.clone()
+        }
+    }
+
+    pub fn limit(&self, scope: Scope, period: Period) -> Option<u64> {
+        match (scope, period) {
+            (Scope::Guild(_), Period::Day) => self.guild_daily,
+            (Scope::Guild(_), Period::Month) => self.guild_monthly,
+            (Scope::User(_), Period::Day) => self.user_daily,
+            (Scope::User(_), Period::Month) => self.user_monthly,
+        }
+    }
+
+    pub fn is_metered(&self, engine: &tts::Engine) -> bool {
+        self.fallback
+            .as_ref()
+            .map_or(true, |fallback| fallback.engine() != *engine)
+    }
+
+    /// Characters counted towards the quota out of the usage.
+    pub fn metered(&self, usage: &Usage, period: Period) -> u64 {
+        usage
+            .iter()
+            .filter(|(engine, _)| {
+                tts::Engine::try_from(engine.as_str()).map_or(true, |e| self.is_metered(&e))
+            })
+            .map(|(_, totals)| totals.get(period))
+            .sum()
+    }
+}
+
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    pub scope: Scope,
+    pub period: Period,
+    pub limit: u64,
+    /// Whether this is the first refusal in a while, and so should be told.
+    pub notify: bool,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let period = match self.period {
+            Period::Day => "daily",
+            Period::Month => "monthly",
+        };
+        match self.scope {
+            Scope::Guild(_) => write!(
+                f,
+                "This server has used up its {} quota of {} characters.",
+                period, self.limit
+            ),
+            Scope::User(user_id) => write!(
+                f,
+                "<@{}> has used up their {} quota of {} characters.",
+                user_id, period, self.limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Records every synthesis in the `tts_usage` table and enforces the quota.
+pub struct UsageStorage {
+    quota: Quota,
+    cache: Mutex<TtlCache<Scope, Usage>>,
+    notified: Mutex<TtlCache<(Scope, Period), ()>>,
+    /// `None` keeps the usage in memory only, as in simulations.
+    pool: Option<MySqlPool>,
+}
+
+impl UsageStorage {
+    pub fn new(pool: MySqlPool, quota: Quota) -> Self {
+        Self {
+            quota,
+            cache: Mutex::new(TtlCache::new(USAGE_CACHE_CAPACITY, USAGE_CACHE_TTL)),
+            notified: Mutex::new(TtlCache::new(USAGE_CACHE_CAPACITY, NOTICE_INTERVAL)),
+            pool: Some(pool),
+        }
+    }
+
+    /// Usage kept in memory only, starting from none, which never resets.
+    pub fn in_memory(quota: Quota) -> Self {
+        Self {
+            quota,
+            cache: Mutex::new(TtlCache::new(USAGE_CACHE_CAPACITY, Duration::MAX)),
+            notified: Mutex::new(TtlCache::new(USAGE_CACHE_CAPACITY, NOTICE_INTERVAL)),
+            pool: None,
+        }
+    }
+
+    pub fn quota(&self) -> &Quota {
+        &self.quota
+    }
+
+    pub async fn record(
+        &self,
+        guild_id: GuildId,
+        user_id: Option<UserId>,
+        engine: &tts::Engine,
+        characters: u64,
+    ) -> anyhow::Result<()> {
+        if let Some(pool) = &self.pool {
+            sqlx::query!(
+                r#"
+INSERT INTO tts_usage (guild_id, user_id, engine, characters)
+VALUES (?, ?, ?, ?)
+                "#,
+                guild_id.0,
+                user_id.map(|id| id.0),
+                engine.to_string(),
+                characters
+            )
+            .execute(pool)
+            .await?;
+        }
+
+        let mut scopes = vec![Scope::Guild(guild_id)];
+        scopes.extend(user_id.map(Scope::User));
+        let mut cache = self.cache.lock();
+        for scope in scopes {
+            if self.pool.is_none() && cache.get(&scope).is_none() {
+                cache.insert(scope, Usage::new());
+            }
+            if let Some(usage) = cache.get_mut(&scope) {
+                let totals = usage.entry(engine.to_string()).or_default();
+                totals.day += characters;
+                totals.month += characters;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn usage(&self, scope: Scope) -> anyhow::Result<Usage> {
+        if let Some(usage) = self.cache.lock().get(&scope) {
+            return Ok(usage);
+        }
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return Ok(Usage::new()),
+        };
+
+        let records = match scope {
+            Scope::Guild(guild_id) => sqlx::query!(
+                r#"
+SELECT
+    engine,
+    CAST(SUM(IF(created_at >= CURDATE(), characters, 0)) AS UNSIGNED) AS "day?: u64",
+    CAST(SUM(characters) AS UNSIGNED) AS "month?: u64"
+FROM tts_usage
+WHERE guild_id = ? AND created_at >= DATE_FORMAT(CURDATE(), '%Y-%m-01')
+GROUP BY engine
+                "#,
+                guild_id.0
+            )
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|r| (r.engine, r.day, r.month))
+            .collect::<Vec<_>>(),
+            Scope::User(user_id) => sqlx::query!(
+                r#"
+SELECT
+    engine,
+    CAST(SUM(IF(created_at >= CURDATE(), characters, 0)) AS UNSIGNED) AS "day?: u64",
+    CAST(SUM(characters) AS UNSIGNED) AS "month?: u64"
+FROM tts_usage
+WHERE user_id = ? AND created_at >= DATE_FORMAT(CURDATE(), '%Y-%m-01')
+GROUP BY engine
+                "#,
+                user_id.0
+            )
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|r| (r.engine, r.day, r.month))
+            .collect::<Vec<_>>(),
+        };
+        let usage: Usage = records
+            .into_iter()
+            .map(|(engine, day, month)| {
+                let totals = Totals {
+                    day: day.unwrap_or(0),
+                    month: month.unwrap_or(0),
+                };
+                (engine, totals)
+            })
+            .collect();
+        self.cache.lock().insert(scope, usage.clone());
+
+        Ok(usage)
+    }
+
+    /// Returns the first limit of `quota` on the guild or the user that has
+    /// been used up, if any.
+    pub async fn check(
+        &self,
+        quota: &Quota,
+        guild_id: GuildId,
+        user_id: UserId,
+    ) -> anyhow::Result<Option<QuotaExceeded>> {
+        for scope in [Scope::Guild(guild_id), Scope::User(user_id)] {
+            let periods = [Period::Day, Period::Month];
+            if periods
+                .iter()
+                .all(|&period| quota.limit(scope, period).is_none())
+            {
+                continue;
+            }
+
+            let usage = self.usage(scope).await?;
+            for period in periods {
+                let limit = match quota.limit(scope, period) {
+                    Some(limit) => limit,
+                    None => continue,
+                };
+                if quota.metered(&usage, period) >= limit {
+                    let mut notified = self.notified.lock();
+                    let notify = notified.get(&(scope, period)).is_none();
+                    if notify {
+                        notified.insert((scope, period), ());
+                    }
+                    return Ok(Some(QuotaExceeded {
+                        scope,
+                        period,
+                        limit,
+                        notify,
+                    }));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::tts::voice_text::VoiceTextOptionsBuilder;
+
+    #[test]
+    fn test_metered() {
+        let mut usage = Usage::new();
+        usage.insert(
+            "voicetext".to_string(),
+            Totals {
+                day: 10,
+                month: 100,
+            },
+        );
+        usage.insert(
+            "voicevox".to_string(),
+            Totals {
+                day: 1,
+                month: 1000,
+            },
+        );
+
+        let mut quota = Quota::default();
+        assert_eq!(quota.metered(&usage, Period::Day), 11);
+        assert_eq!(quota.metered(&usage, Period::Month), 1100);
+
+        quota.fallback = Some(tts::Options::VoiceTextOptions(
+            VoiceTextOptionsBuilder::default()
+                .speaker("show".try_into().unwrap())
+                .build()
+                .unwrap(),
+        ));
+        assert!(!quota.is_metered(&tts::Engine::VoiceText));
+        assert_eq!(quota.metered(&usage, Period::Month), 1000);
+    }
+
+    #[test]
+    fn test_for_guild() {
+        let quota = Quota {
+            guild_daily: Some(1000),
+            guild_monthly: Some(10000),
+            ..Default::default()
+        };
+        let settings = GuildSettings {
+            daily_quota: Some(100),
+            ..Default::default()
+        };
+        let guild = Scope::Guild(GuildId(1));
+        assert_eq!(
+            quota.for_guild(&settings).limit(guild, Period::Day),
+            Some(100)
+        );
+        assert_eq!(
+            quota.for_guild(&settings).limit(guild, Period::Month),
+            Some(10000)
+        );
+        let settings = GuildSettings::default();
+        assert_eq!(
+            quota.for_guild(&settings).limit(guild, Period::Day),
+            Some(1000)
+        );
+    }
+}